@@ -0,0 +1,78 @@
+//! Drives the renderer from a hand-rolled loop on a device created outside of it,
+//! without a window or event loop, and saves the last frame.
+//!
+//! `cargo run --example frame_loop`
+
+use rt_bevy::capabilities::Capabilities;
+use rt_bevy::config::AppConfig;
+use rt_bevy::renderer::{Renderer, OFFSCREEN_FORMAT};
+use rt_bevy::scene::Scene;
+
+const WIDTH: u32 = 400;
+const HEIGHT: u32 = 300;
+const FRAMES: u32 = 32;
+
+fn main() -> anyhow::Result<()> {
+	env_logger::init();
+
+	let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+	let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+		power_preference: wgpu::PowerPreference::default(),
+		force_fallback_adapter: false,
+		compatible_surface: None,
+	}))
+	.ok_or_else(|| anyhow::anyhow!("No adapter available"))?;
+	let capabilities = Capabilities::new(&adapter);
+	let (device, queue) = pollster::block_on(adapter.request_device(
+		&wgpu::DeviceDescriptor {
+			label: Some("Frame Loop Device"),
+			features: capabilities.features(),
+			limits: capabilities.limits(&adapter),
+		},
+		None,
+	))?;
+
+	let mut scene = Scene::example();
+	let mut renderer = Renderer::from_device(
+		device,
+		queue,
+		OFFSCREEN_FORMAT,
+		&scene.world_spheres(),
+		&AppConfig::default(),
+	)?;
+	renderer.resize(WIDTH, HEIGHT);
+	renderer.set_scene(&scene);
+
+	// The target is owned by the caller, like a surface texture would be
+	let target = renderer.device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Frame Loop Target"),
+		size: wgpu::Extent3d {
+			width: WIDTH,
+			height: HEIGHT,
+			depth_or_array_layers: 1,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: OFFSCREEN_FORMAT,
+		usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+		view_formats: &[],
+	});
+
+	for frame in 0..FRAMES {
+		// Turn the camera for the first half, then let the image converge
+		if frame < FRAMES / 2 {
+			scene.camera.orbit(0.02, 0.0);
+			renderer.update_camera_transform(&scene.camera);
+		}
+		renderer.render(&target);
+	}
+
+	renderer.capture("frame_loop.png");
+	renderer.wait_for_captures();
+	println!(
+		"Rendered {FRAMES} frames, {} samples per pixel",
+		renderer.sample_count()
+	);
+	Ok(())
+}
@@ -0,0 +1,283 @@
+use std::borrow::Cow;
+use wgpu::util::DeviceExt;
+
+/// Maximum number of times the bright parts of the image are halved in size and blurred
+const LEVEL_COUNT: u32 = 5;
+
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+	threshold: f32,
+	_padding: [f32; 3],
+}
+
+// Layout of `Bloom` in bloom.wgsl
+const _: () = assert!(std::mem::size_of::<BloomUniform>() == 16);
+
+/// Pipelines that blur the bright parts of an hdr texture into a [`BloomTarget`]
+pub(crate) struct Bloom {
+	source_layout: wgpu::BindGroupLayout,
+	/// Linear sampler, also used to read the result in the post pass
+	pub(crate) sampler: wgpu::Sampler,
+	uniform_buffer: wgpu::Buffer,
+	prefilter: wgpu::RenderPipeline,
+	downsample: wgpu::RenderPipeline,
+	upsample: wgpu::RenderPipeline,
+}
+
+/// Size dependent textures of [`Bloom`]
+pub(crate) struct BloomTarget {
+	/// Each level is half the size of the previous one, the first is half the size of the hdr texture
+	views: Vec<wgpu::TextureView>,
+	/// Reads the hdr texture
+	hdr_bind_group: wgpu::BindGroup,
+	/// Read the level with the same index
+	level_bind_groups: Vec<wgpu::BindGroup>,
+}
+
+impl BloomTarget {
+	/// Largest level, which holds the final result
+	pub(crate) fn view(&self) -> &wgpu::TextureView {
+		&self.views[0]
+	}
+}
+
+impl Bloom {
+	pub(crate) fn new(device: &wgpu::Device, format: wgpu::TextureFormat) -> Self {
+		let source_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Bloom Bind Group Layout"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						sample_type: wgpu::TextureSampleType::Float { filterable: true },
+						view_dimension: wgpu::TextureViewDimension::D2,
+						multisampled: false,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 2,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		});
+
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Bloom Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+
+		let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Bloom Buffer"),
+			contents: bytemuck::cast_slice(&[BloomUniform {
+				threshold: 1.0,
+				_padding: [0.0; 3],
+			}]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("Bloom Shader"),
+			source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("bloom.wgsl"))),
+		});
+		let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Bloom Pipeline Layout"),
+			bind_group_layouts: &[&source_layout],
+			push_constant_ranges: &[],
+		});
+		let create_pipeline = |entry_point, blend| {
+			device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+				label: Some(&format!("Bloom Pipeline {entry_point}")),
+				layout: Some(&layout),
+				vertex: wgpu::VertexState {
+					module: &shader,
+					entry_point: "vs_main",
+					buffers: &[],
+				},
+				fragment: Some(wgpu::FragmentState {
+					module: &shader,
+					entry_point,
+					targets: &[Some(wgpu::ColorTargetState {
+						format,
+						blend,
+						write_mask: wgpu::ColorWrites::ALL,
+					})],
+				}),
+				primitive: wgpu::PrimitiveState::default(),
+				depth_stencil: None,
+				multisample: wgpu::MultisampleState::default(),
+				multiview: None,
+			})
+		};
+		let additive = wgpu::BlendComponent {
+			src_factor: wgpu::BlendFactor::One,
+			dst_factor: wgpu::BlendFactor::One,
+			operation: wgpu::BlendOperation::Add,
+		};
+
+		Self {
+			prefilter: create_pipeline("fs_prefilter", None),
+			downsample: create_pipeline("fs_downsample", None),
+			upsample: create_pipeline(
+				"fs_upsample",
+				Some(wgpu::BlendState {
+					color: additive,
+					alpha: additive,
+				}),
+			),
+			source_layout,
+			sampler,
+			uniform_buffer,
+		}
+	}
+
+	pub(crate) fn create_target(
+		&self,
+		device: &wgpu::Device,
+		hdr: &wgpu::Texture,
+		hdr_view: &wgpu::TextureView,
+	) -> BloomTarget {
+		let (width, height) = ((hdr.width() / 2).max(1), (hdr.height() / 2).max(1));
+		// Stop before the smaller side would shrink below one pixel
+		let level_count = LEVEL_COUNT.min(width.min(height).ilog2() + 1);
+		// Separate textures instead of mip levels, because the gl backend can't sample single mip levels
+		let views: Vec<_> = (0..level_count)
+			.map(|level| {
+				let texture = device.create_texture(&wgpu::TextureDescriptor {
+					label: Some("Bloom Texture"),
+					size: wgpu::Extent3d {
+						width: (width >> level).max(1),
+						height: (height >> level).max(1),
+						depth_or_array_layers: 1,
+					},
+					mip_level_count: 1,
+					sample_count: 1,
+					dimension: wgpu::TextureDimension::D2,
+					format: hdr.format(),
+					usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+						| wgpu::TextureUsages::TEXTURE_BINDING,
+					view_formats: &[],
+				});
+				texture.create_view(&wgpu::TextureViewDescriptor::default())
+			})
+			.collect();
+
+		BloomTarget {
+			hdr_bind_group: self.create_source_bind_group(device, hdr_view),
+			level_bind_groups: views
+				.iter()
+				.map(|view| self.create_source_bind_group(device, view))
+				.collect(),
+			views,
+		}
+	}
+
+	fn create_source_bind_group(
+		&self,
+		device: &wgpu::Device,
+		source: &wgpu::TextureView,
+	) -> wgpu::BindGroup {
+		device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Bloom Bind Group"),
+			layout: &self.source_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(source),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::Sampler(&self.sampler),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: self.uniform_buffer.as_entire_binding(),
+				},
+			],
+		})
+	}
+
+	/// Writes the blurred parts of the hdr texture brighter than `threshold` into `target`
+	pub(crate) fn render(
+		&self,
+		queue: &wgpu::Queue,
+		encoder: &mut wgpu::CommandEncoder,
+		target: &BloomTarget,
+		threshold: f32,
+	) {
+		queue.write_buffer(
+			&self.uniform_buffer,
+			0,
+			bytemuck::cast_slice(&[BloomUniform {
+				threshold,
+				_padding: [0.0; 3],
+			}]),
+		);
+
+		let level_count = target.views.len();
+		draw(
+			encoder,
+			&self.prefilter,
+			&target.hdr_bind_group,
+			&target.views[0],
+			wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+		);
+		for level in 1..level_count {
+			draw(
+				encoder,
+				&self.downsample,
+				&target.level_bind_groups[level - 1],
+				&target.views[level],
+				wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+			);
+		}
+		// Every level gets the blurred sum of all smaller levels added to it
+		for level in (0..level_count - 1).rev() {
+			draw(
+				encoder,
+				&self.upsample,
+				&target.level_bind_groups[level + 1],
+				&target.views[level],
+				wgpu::LoadOp::Load,
+			);
+		}
+	}
+}
+
+fn draw(
+	encoder: &mut wgpu::CommandEncoder,
+	pipeline: &wgpu::RenderPipeline,
+	source: &wgpu::BindGroup,
+	target: &wgpu::TextureView,
+	load: wgpu::LoadOp<wgpu::Color>,
+) {
+	let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+		label: Some("Bloom Pass"),
+		color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+			view: target,
+			resolve_target: None,
+			ops: wgpu::Operations { load, store: true },
+		})],
+		depth_stencil_attachment: None,
+	});
+	pass.set_pipeline(pipeline);
+	pass.set_bind_group(0, source, &[]);
+	pass.draw(0..3, 0..1);
+}
@@ -0,0 +1,72 @@
+// Bright parts of the hdr image, blurred over several levels of halving size and added back by post.wgsl
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  // texture coordinates, (0, 0) is the top left corner
+  @location(0) uv: vec2<f32>,
+};
+
+// Triangle covering the whole target, which needs no vertex buffer
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.position = vec4(uv * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+struct Bloom {
+  // brightness above which pixels start to glow
+  threshold: f32,
+}
+
+@group(0)
+@binding(0)
+var source: texture_2d<f32>;
+@group(0)
+@binding(1)
+var source_sampler: sampler;
+@group(0)
+@binding(2)
+var<uniform> bloom: Bloom;
+
+// Average of the 4x4 source texels around uv, the source is twice the size of the target
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    // every bilinear sample between four texels averages them
+    return (textureSample(source, source_sampler, uv + texel * vec2(-1.0, -1.0)).rgb
+        + textureSample(source, source_sampler, uv + texel * vec2(1.0, -1.0)).rgb
+        + textureSample(source, source_sampler, uv + texel * vec2(-1.0, 1.0)).rgb
+        + textureSample(source, source_sampler, uv + texel * vec2(1.0, 1.0)).rgb) * 0.25;
+}
+
+// Downsamples the hdr image and keeps only what is brighter than the threshold
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - bloom.threshold, 0.0) / max(brightness, 0.0001);
+    return vec4(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter of the smaller level, added to the larger one through blending
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(source));
+    var color = textureSample(source, source_sampler, in.uv).rgb * 4.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, 0.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, 0.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(0.0, -1.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(0.0, 1.0)).rgb * 2.0;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, -1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, -1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(-1.0, 1.0)).rgb;
+    color += textureSample(source, source_sampler, in.uv + texel * vec2(1.0, 1.0)).rgb;
+    return vec4(color / 16.0, 1.0);
+}
@@ -0,0 +1,168 @@
+use glam::{Quat, Vec2, Vec3};
+use serde::{Deserialize, Serialize};
+
+/// Pitch stops just short of looking straight up or down, where the right direction is undefined
+const MAX_PITCH: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+/// Orbit camera looking at `origin` from `distance` units away
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Camera {
+	/// Point the camera orbits around and looks at
+	pub origin: Vec3,
+	/// Rotation around the y axis in radians, `0.0` looks along +z
+	pub yaw: f32,
+	/// Rotation above (positive) or below (negative) the horizon in radians
+	pub pitch: f32,
+	/// Rotation around the view direction in radians, positive values tilt the horizon clockwise
+	pub roll: f32,
+	pub distance: f32,
+	/// Vertical field of view in degrees
+	pub fov: f32,
+	/// Hits closer to the camera than this are ignored
+	pub near: f32,
+	/// Hits further away from the camera than this are ignored
+	pub far: f32,
+}
+
+impl Default for Camera {
+	fn default() -> Self {
+		Self {
+			origin: Vec3::ZERO,
+			yaw: 0.0,
+			pitch: 0.0,
+			roll: 0.0,
+			distance: 2.0,
+			fov: 90.0,
+			near: 0.001,
+			far: 1000.0,
+		}
+	}
+}
+
+impl Camera {
+	pub fn forward(&self) -> Vec3 {
+		Vec3::new(
+			self.pitch.cos() * self.yaw.sin(),
+			self.pitch.sin(),
+			self.pitch.cos() * self.yaw.cos(),
+		)
+	}
+
+	pub fn right(&self) -> Vec3 {
+		self.roll_rotation() * Vec3::Y.cross(self.forward()).normalize()
+	}
+
+	pub fn up(&self) -> Vec3 {
+		self.forward().cross(self.right())
+	}
+
+	fn roll_rotation(&self) -> Quat {
+		Quat::from_axis_angle(self.forward(), self.roll)
+	}
+
+	pub fn position(&self) -> Vec3 {
+		self.origin - self.forward() * self.distance
+	}
+
+	/// Rotates around `origin`, pitch stops just short of looking straight up or down
+	pub fn orbit(&mut self, yaw: f32, pitch: f32) {
+		self.yaw += yaw;
+		self.pitch = (self.pitch + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+	}
+
+	/// Tilts the horizon by `angle` radians, wrapping around after a full turn
+	pub fn roll_by(&mut self, angle: f32) {
+		use std::f32::consts::{PI, TAU};
+		self.roll = (self.roll + angle + PI).rem_euclid(TAU) - PI;
+	}
+
+	/// Orbits `center` from far enough away to see all of a sphere with `radius` around it,
+	/// keeping the direction
+	pub fn frame(&mut self, center: Vec3, radius: f32) {
+		self.origin = center;
+		self.distance = radius / (self.fov.to_radians() / 2.0).sin();
+		self.far = self.far.max(self.distance + radius);
+	}
+
+	/// Places the camera at `eye` looking at `target`, which becomes the new `origin`, and rolls it so `up`
+	/// points up in the image as far as possible.
+	///
+	/// Looking straight up or down is clamped like [`Camera::orbit`]. Keeps the current direction if `eye`
+	/// and `target` are the same point, and levels the horizon if `up` is parallel to the view direction
+	pub fn look_at(&mut self, eye: Vec3, target: Vec3, up: Vec3) {
+		let offset = target - eye;
+		self.origin = target;
+		self.distance = offset.length();
+		self.roll = 0.0;
+		let Some(forward) = offset.try_normalize() else {
+			return;
+		};
+		// Straight up or down the yaw only turns the image, so it is taken from `up` instead
+		let horizontal = if Vec2::new(forward.x, forward.z).length() > 1e-4 {
+			forward
+		} else {
+			up * -forward.y.signum()
+		};
+		self.yaw = horizontal.x.atan2(horizontal.z);
+		// Rounding can push the length of `forward` slightly above 1
+		self.pitch = forward
+			.y
+			.clamp(-1.0, 1.0)
+			.asin()
+			.clamp(-MAX_PITCH, MAX_PITCH);
+
+		let forward = self.forward();
+		if let Some(up) = up.reject_from(forward).try_normalize() {
+			let level = self.up();
+			self.roll = forward.dot(level.cross(up)).atan2(level.dot(up));
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn assert_close(actual: Vec3, expected: Vec3) {
+		assert!(
+			actual.abs_diff_eq(expected, 1e-4),
+			"{actual} is not close to {expected}"
+		);
+	}
+
+	#[test]
+	fn look_at_points_from_eye_to_target() {
+		let (eye, target) = (Vec3::new(1.0, 2.0, 3.0), Vec3::new(-2.0, 0.5, 1.0));
+		let mut camera = Camera::default();
+		camera.look_at(eye, target, Vec3::Y);
+		assert_close(camera.forward(), (target - eye).normalize());
+		assert_close(camera.position(), eye);
+		assert_eq!(camera.origin, target);
+		assert!(camera.roll.abs() < 1e-5);
+	}
+
+	#[test]
+	fn look_at_rolls_towards_up() {
+		let mut camera = Camera::default();
+		camera.look_at(Vec3::ZERO, Vec3::Z, Vec3::new(1.0, 0.0, 1.0));
+		assert_close(camera.forward(), Vec3::Z);
+		assert_close(camera.up(), Vec3::X);
+	}
+
+	#[test]
+	fn look_at_handles_degenerate_input() {
+		let mut camera = Camera::default();
+		// Up parallel to the view direction levels the horizon
+		camera.look_at(Vec3::Y, Vec3::ZERO, Vec3::Y);
+		assert!(camera.forward().is_finite() && camera.up().is_finite());
+		assert!(camera.roll.abs() < 1e-5);
+		// Straight down the yaw follows up
+		camera.look_at(Vec3::Y, Vec3::ZERO, Vec3::X);
+		assert!(camera.up().dot(Vec3::X) > 0.99);
+		// Eye and target at the same point keep the direction
+		let forward = camera.forward();
+		camera.look_at(Vec3::ONE, Vec3::ONE, Vec3::Y);
+		assert_close(camera.forward(), forward);
+	}
+}
@@ -0,0 +1,107 @@
+use std::fmt;
+
+/// Bind groups the path tracing pipeline uses
+pub(crate) const REQUIRED_BIND_GROUPS: u32 = 4;
+/// Storage buffers the path tracing fragment shader binds: spheres, emitters, boxes, lights and the accumulation
+pub(crate) const REQUIRED_STORAGE_BUFFERS: u32 = 5;
+
+/// What the adapter supports beyond the downlevel defaults, detected once by
+/// [`Renderer::new`](crate::renderer::Renderer::new) and requested from the device.
+///
+/// [`Capabilities::features`] and [`Capabilities::limits`] describe the device
+/// to create for [`Renderer::from_device`](crate::renderer::Renderer::from_device)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+	/// Gpu timings of the render passes, see [`Renderer::pass_timings`](crate::renderer::Renderer::pass_timings)
+	pub timestamp_queries: bool,
+	/// Binding arrays of storage buffers, only reported in the stats
+	pub binding_arrays: bool,
+	/// Not used by the renderer yet
+	pub push_constants: bool,
+	/// Largest storage buffer binding in bytes, limits the spheres per scene
+	pub max_storage_buffer_binding_size: u32,
+	/// Largest buffer in bytes
+	pub max_buffer_size: u64,
+	/// Limits the material textures
+	pub max_texture_array_layers: u32,
+	pub max_bind_groups: u32,
+	/// The downlevel defaults allow fewer than the renderer needs, see [`Capabilities::limits`]
+	pub max_storage_buffers_per_shader_stage: u32,
+}
+
+impl Capabilities {
+	/// On the web none of the optional features are requested, so the renderer runs on the same fallback
+	/// path in every browser, whatever features their WebGPU implementation exposes
+	pub fn new(adapter: &wgpu::Adapter) -> Self {
+		let features = if cfg!(target_arch = "wasm32") {
+			wgpu::Features::empty()
+		} else {
+			adapter.features()
+		};
+		Self::from_parts(features, adapter.limits())
+	}
+
+	/// What an existing device was created with
+	pub fn from_device(device: &wgpu::Device) -> Self {
+		Self::from_parts(device.features(), device.limits())
+	}
+
+	fn from_parts(features: wgpu::Features, limits: wgpu::Limits) -> Self {
+		Self {
+			timestamp_queries: features.contains(wgpu::Features::TIMESTAMP_QUERY),
+			binding_arrays: features.contains(
+				wgpu::Features::BUFFER_BINDING_ARRAY
+					| wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY,
+			),
+			push_constants: features.contains(wgpu::Features::PUSH_CONSTANTS)
+				&& limits.max_push_constant_size > 0,
+			max_storage_buffer_binding_size: limits.max_storage_buffer_binding_size,
+			max_buffer_size: limits.max_buffer_size,
+			max_texture_array_layers: limits.max_texture_array_layers,
+			max_bind_groups: limits.max_bind_groups,
+			max_storage_buffers_per_shader_stage: limits.max_storage_buffers_per_shader_stage,
+		}
+	}
+
+	/// Optional features to request, only the supported ones
+	pub fn features(&self) -> wgpu::Features {
+		let mut features = wgpu::Features::empty();
+		features.set(wgpu::Features::TIMESTAMP_QUERY, self.timestamp_queries);
+		features.set(
+			wgpu::Features::BUFFER_BINDING_ARRAY | wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY,
+			self.binding_arrays,
+		);
+		features
+	}
+
+	/// The downlevel defaults, raised to everything the adapter allows for the sizes the scene depends on
+	/// and the number of storage buffers the shader binds
+	pub fn limits(&self, adapter: &wgpu::Adapter) -> wgpu::Limits {
+		// Make sure we use the texture resolution limits from the adapter, so we can support images the size of the swapchain.
+		wgpu::Limits {
+			max_storage_buffer_binding_size: self.max_storage_buffer_binding_size,
+			max_buffer_size: self.max_buffer_size,
+			max_texture_array_layers: self.max_texture_array_layers,
+			max_storage_buffers_per_shader_stage: self.max_storage_buffers_per_shader_stage,
+			..wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits())
+		}
+	}
+}
+
+impl fmt::Display for Capabilities {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+		write!(
+			f,
+			"timestamp queries: {}, binding arrays: {}, push constants: {}, \
+			storage buffer bindings up to {} MiB, {} storage buffers per stage, {} texture array layers, {} bind groups",
+			yes_no(self.timestamp_queries),
+			yes_no(self.binding_arrays),
+			yes_no(self.push_constants),
+			self.max_storage_buffer_binding_size / (1 << 20),
+			self.max_storage_buffers_per_shader_stage,
+			self.max_texture_array_layers,
+			self.max_bind_groups,
+		)
+	}
+}
@@ -0,0 +1,150 @@
+use anyhow::Context;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread::JoinHandle;
+
+/// Copy of a rendered frame on its way from the gpu to a png file,
+/// or an exr file for the `Rgba16Float` hdr image
+pub(crate) struct PendingCapture {
+	buffer: wgpu::Buffer,
+	format: wgpu::TextureFormat,
+	width: u32,
+	height: u32,
+	padded_bytes_per_row: u32,
+	path: PathBuf,
+	/// Set once mapping has been requested, receives the result of the mapping
+	mapped: Option<Receiver<Result<(), wgpu::BufferAsyncError>>>,
+}
+
+impl PendingCapture {
+	/// Records a copy of `texture` into a readback buffer,
+	/// [`PendingCapture::map`] has to be called after `encoder` was submitted
+	pub(crate) fn new(
+		device: &wgpu::Device,
+		encoder: &mut wgpu::CommandEncoder,
+		texture: &wgpu::Texture,
+		path: PathBuf,
+	) -> Self {
+		let (width, height) = (texture.width(), texture.height());
+		let bytes_per_row = width * texture.format().describe().block_size as u32;
+		// Rows of the copy destination have to be aligned
+		let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+			* wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+
+		let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Capture Buffer"),
+			size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+
+		encoder.copy_texture_to_buffer(
+			texture.as_image_copy(),
+			wgpu::ImageCopyBuffer {
+				buffer: &buffer,
+				layout: wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: Some(padded_bytes_per_row.try_into().unwrap()),
+					rows_per_image: None,
+				},
+			},
+			texture.size(),
+		);
+
+		Self {
+			buffer,
+			format: texture.format(),
+			width,
+			height,
+			padded_bytes_per_row,
+			path,
+			mapped: None,
+		}
+	}
+
+	/// Requests mapping the readback buffer, which completes during a later `device.poll`
+	pub(crate) fn map(&mut self) {
+		let (sender, receiver) = mpsc::channel();
+		self.buffer
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, move |result| {
+				// The capture may have been dropped in the meantime
+				let _ = sender.send(result);
+			});
+		self.mapped = Some(receiver);
+	}
+
+	/// Writes the png on a separate thread once the buffer is mapped, its handle is pushed to `saving`.
+	/// The web can't spawn threads, so there it is written right away.
+	/// Returns `false` while the mapping is still in flight
+	pub(crate) fn try_finish(&self, saving: &mut Vec<JoinHandle<()>>) -> bool {
+		let Some(mapped) = &self.mapped else {
+			return false;
+		};
+		match mapped.try_recv() {
+			Err(TryRecvError::Empty) => return false,
+			Err(TryRecvError::Disconnected) => eprintln!("Capture buffer was never mapped"),
+			Ok(Err(err)) => eprintln!("Failed to map capture buffer: {err}"),
+			Ok(Ok(())) => {
+				let pixels = self.read_pixels();
+				let (format, width, height) = (self.format, self.width, self.height);
+				let path = self.path.clone();
+				let save = move || {
+					let saved = match format {
+						wgpu::TextureFormat::Rgba16Float => save_exr(&path, width, height, &pixels),
+						_ => save_png(&path, width, height, &pixels),
+					};
+					match saved {
+						Ok(()) => println!("Saved capture to {}", path.display()),
+						Err(err) => eprintln!("Failed to save capture: {err}"),
+					}
+				};
+				if cfg!(target_arch = "wasm32") {
+					save();
+				} else {
+					saving.push(std::thread::spawn(save));
+				}
+			}
+		}
+		true
+	}
+
+	fn read_pixels(&self) -> Vec<u8> {
+		let bytes_per_row = (self.width * self.format.describe().block_size as u32) as usize;
+		let pixels = {
+			let data = self.buffer.slice(..).get_mapped_range();
+			data.chunks(self.padded_bytes_per_row as usize)
+				.flat_map(|row| &row[..bytes_per_row])
+				.copied()
+				.collect()
+		};
+		self.buffer.unmap();
+		pixels
+	}
+}
+
+/// Writes 8 bit rgba `pixels` to a png that is marked as sRGB,
+/// which every target of the renderer is encoded in
+fn save_png(path: &Path, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()> {
+	let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+	encoder.set_color(png::ColorType::Rgba);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.set_srgb(png::SrgbRenderingIntent::Perceptual);
+	encoder.write_header()?.write_image_data(pixels)?;
+	Ok(())
+}
+
+/// Writes `Rgba16Float` `pixels` to an exr file, keeping the linear values
+fn save_exr(path: &Path, width: u32, height: u32, pixels: &[u8]) -> anyhow::Result<()> {
+	// Copied because the readback bytes don't have to be aligned for f16
+	let pixels = bytemuck::pod_collect_to_vec::<u8, half::f16>(pixels)
+		.into_iter()
+		.map(half::f16::to_f32)
+		.collect();
+	let image = image::Rgba32FImage::from_raw(width, height, pixels)
+		.context("Capture buffer should contain width * height pixels")?;
+	image.save_with_format(path, image::ImageFormat::OpenExr)?;
+	Ok(())
+}
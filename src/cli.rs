@@ -0,0 +1,247 @@
+use clap::{ArgGroup, Parser};
+use std::path::{Path, PathBuf};
+
+use rt_bevy::config::{AppConfig, DevicePolling, FrameScheduling};
+
+const DEFAULT_HEADLESS_FRAMES: u32 = 64;
+const DEFAULT_TURNTABLE_FRAMES: u32 = 36;
+const DEFAULT_HEADLESS_OUTPUT: &str = "render.png";
+const DEFAULT_TURNTABLE_OUTPUT: &str = "turntable.png";
+const DEFAULT_HEADLESS_SIZE: (u32, u32) = (800, 600);
+
+/// Path traces spheres, in a window unless --headless, --turntable or --bench-frames is passed
+#[derive(Debug, Parser)]
+#[command(version)]
+#[command(group(ArgGroup::new("offline").args(["headless", "turntable"]).multiple(true)))]
+pub struct Args {
+	/// Scene file to load, pass it multiple times to switch between scenes with the number keys,
+	/// `-` reads the scene from stdin
+	#[arg(long = "scene", value_name = "PATH")]
+	pub scenes: Vec<PathBuf>,
+
+	/// Point cloud to add a sphere for every point of to the scene, a .ply or .csv file,
+	/// see `rt_bevy::points::load_points` for the columns
+	#[arg(long, value_name = "PATH")]
+	pub points: Option<PathBuf>,
+
+	/// Radius of points that don't have one in the point cloud file
+	#[arg(long, default_value_t = 0.02, value_name = "RADIUS")]
+	pub point_radius: f32,
+
+	/// Render without a window and save the image
+	#[arg(long)]
+	pub headless: bool,
+
+	/// Save one image per step of a full camera rotation around its origin, implies --headless
+	#[arg(long)]
+	pub turntable: bool,
+
+	/// Render this many frames as fast as possible without saving anything and print how long they took
+	#[arg(long, value_name = "N", conflicts_with_all = ["headless", "turntable"], value_parser = clap::value_parser!(u32).range(1..))]
+	pub bench_frames: Option<u32>,
+
+	/// Width of the window or image in pixels
+	#[arg(long, requires = "height")]
+	pub width: Option<u32>,
+
+	/// Height of the window or image in pixels
+	#[arg(long, requires = "width")]
+	pub height: Option<u32>,
+
+	/// Frames accumulated into the image, or turntable steps
+	/// [default: 64 for --headless, 36 for --turntable]
+	#[arg(long, requires = "offline", value_parser = clap::value_parser!(u32).range(1..))]
+	pub frames: Option<u32>,
+
+	/// Where to save the image, turntable images get their step appended to the name.
+	/// `.exr` files get the linear hdr image before post processing, anything else a png
+	#[arg(long, requires = "offline", value_name = "PATH")]
+	pub output: Option<PathBuf>,
+
+	/// Render the --headless image in square tiles of this many pixels and stitch them together,
+	/// for images larger than the gpu can render at once. Only png output is supported
+	#[arg(long, requires = "headless", conflicts_with = "turntable", value_name = "PIXELS", value_parser = clap::value_parser!(u32).range(1..))]
+	pub tile_size: Option<u32>,
+
+	/// Skip the --headless render if the output exists and was rendered from the same scene, size and frames,
+	/// which is recorded in a `.hash` file next to it
+	#[arg(long, requires = "headless", conflicts_with = "turntable")]
+	pub skip_unchanged: bool,
+
+	/// Also save these passes of the --headless image next to it, see [`Aov`]
+	#[arg(long, requires = "headless", conflicts_with_all = ["turntable", "tile_size"], value_delimiter = ',', value_name = "AOV")]
+	pub aov: Vec<Aov>,
+
+	/// Store spheres in half precision on the gpu
+	#[arg(long)]
+	pub packed_spheres: bool,
+
+	/// Output premultiplied alpha
+	#[arg(long)]
+	pub premultiplied_alpha: bool,
+
+	/// Most frames drawn per second, 0 for no limit
+	#[arg(long, default_value_t = 0, value_name = "FPS")]
+	pub max_fps: u32,
+
+	/// Present frames as soon as they are done instead of waiting for vertical sync
+	#[arg(long)]
+	pub no_vsync: bool,
+
+	/// Busy poll the event loop instead of sleeping between frames
+	#[arg(long)]
+	pub poll: bool,
+
+	/// Poll the gpu after every frame, which can even out frame pacing on some platforms
+	#[arg(long)]
+	pub poll_device: bool,
+
+	/// Stop drawing once this many samples per pixel have been accumulated, until the next input
+	#[arg(long, value_name = "SAMPLES", conflicts_with = "poll")]
+	pub idle_after: Option<u32>,
+
+	/// Warn about spheres hidden inside other spheres whenever the scene changes
+	#[arg(long)]
+	pub validate_spheres: bool,
+
+	/// Keep the previous frame in a history texture for temporal effects, costs a copy per frame
+	#[arg(long)]
+	pub temporal_history: bool,
+
+	/// Prefer the integrated gpu if there are several
+	#[arg(long)]
+	pub low_power: bool,
+
+	/// Prefer the discrete gpu if there are several
+	#[arg(long, conflicts_with = "low_power")]
+	pub high_performance: bool,
+}
+
+/// Extra image of a headless render for compositing and denoising, saved as `{stem}.{name}.{extension}` next to it
+#[derive(Copy, Clone, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Aov {
+	/// World space normals from -1 to 1 in a linear exr, zero where the sky is visible
+	Normal,
+	/// Unlit surface colors in an sRGB png
+	Albedo,
+	/// Distance to the nearest surface through the pixel centers in a linear exr
+	Depth,
+}
+
+impl Aov {
+	/// Where the pass of a render saved at `output` goes
+	pub fn path(self, output: &Path) -> PathBuf {
+		let (name, extension) = match self {
+			Self::Normal => ("normal", "exr"),
+			Self::Albedo => ("albedo", "png"),
+			Self::Depth => ("depth", "exr"),
+		};
+		let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+		output.with_file_name(format!("{stem}.{name}.{extension}"))
+	}
+}
+
+/// What `main` does after parsing the arguments
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RunMode {
+	/// Open a window
+	Interactive,
+	/// Accumulate `frames` frames and save the result to `output`, in tiles of `tile_size` pixels if set.
+	/// With `skip_unchanged` nothing is rendered if the hash file next to `output` matches the scene.
+	/// Every pass in `aovs` is rendered separately after the image
+	Headless {
+		frames: u32,
+		output: PathBuf,
+		tile_size: Option<u32>,
+		skip_unchanged: bool,
+		aovs: Vec<Aov>,
+	},
+	/// Save `frames` images while the camera rotates once around its origin
+	Turntable { frames: u32, output: PathBuf },
+	/// Time `frames` frames while the camera rotates once around its origin
+	Bench { frames: u32 },
+}
+
+impl Args {
+	pub fn run_mode(&self) -> RunMode {
+		let output = |default: &str| self.output.clone().unwrap_or_else(|| default.into());
+		if let Some(frames) = self.bench_frames {
+			RunMode::Bench { frames }
+		} else if self.turntable {
+			RunMode::Turntable {
+				frames: self.frames.unwrap_or(DEFAULT_TURNTABLE_FRAMES),
+				output: output(DEFAULT_TURNTABLE_OUTPUT),
+			}
+		} else if self.headless {
+			RunMode::Headless {
+				frames: self.frames.unwrap_or(DEFAULT_HEADLESS_FRAMES),
+				output: output(DEFAULT_HEADLESS_OUTPUT),
+				tile_size: self.tile_size,
+				skip_unchanged: self.skip_unchanged,
+				aovs: self.aov.clone(),
+			}
+		} else {
+			RunMode::Interactive
+		}
+	}
+
+	pub fn app_config(&self) -> AppConfig {
+		AppConfig {
+			packed_spheres: self.packed_spheres,
+			device_polling: if self.poll_device {
+				DevicePolling::EveryFrame
+			} else {
+				DevicePolling::OnDemand
+			},
+			premultiplied_alpha: self.premultiplied_alpha,
+			max_fps: self.max_fps,
+			present_mode: if self.no_vsync {
+				wgpu::PresentMode::AutoNoVsync
+			} else {
+				wgpu::PresentMode::Fifo
+			},
+			frame_scheduling: match self.idle_after {
+				Some(samples) => FrameScheduling::OnDemand { samples },
+				None if self.poll => FrameScheduling::Poll,
+				None => FrameScheduling::Timed,
+			},
+			window_size: self.size(),
+			validate_spheres: self.validate_spheres,
+			power_preference: if self.low_power {
+				wgpu::PowerPreference::LowPower
+			} else if self.high_performance {
+				wgpu::PowerPreference::HighPerformance
+			} else {
+				wgpu::PowerPreference::default()
+			},
+			temporal_history: self.temporal_history,
+		}
+	}
+
+	/// Size passed with --width and --height
+	pub fn size(&self) -> Option<(u32, u32)> {
+		self.width.zip(self.height)
+	}
+
+	/// Image size of the headless modes
+	pub fn headless_size(&self) -> (u32, u32) {
+		self.size().unwrap_or(DEFAULT_HEADLESS_SIZE)
+	}
+}
+
+/// File next to a headless render at `output` that records what it was rendered from
+pub fn hash_path(output: &Path) -> PathBuf {
+	let mut name = output.file_name().unwrap_or_default().to_owned();
+	name.push(".hash");
+	output.with_file_name(name)
+}
+
+/// `path` with `_{step}` appended to the file name, keeping the extension
+pub fn numbered_path(path: &Path, step: u32) -> PathBuf {
+	let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+	let mut name = format!("{stem}_{step:03}");
+	if let Some(extension) = path.extension() {
+		name = format!("{name}.{}", extension.to_string_lossy());
+	}
+	path.with_file_name(name)
+}
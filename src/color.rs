@@ -0,0 +1,58 @@
+use glam::Vec4;
+
+/// Converts an sRGB encoded color, as shown by most color pickers, to the linear values the shader expects.
+/// Alpha is already linear and is kept as is.
+pub fn srgb_to_linear(srgb: Vec4) -> Vec4 {
+	let [r, g, b, a] = srgb.to_array();
+	Vec4::new(
+		channel_to_linear(r),
+		channel_to_linear(g),
+		channel_to_linear(b),
+		a,
+	)
+}
+
+/// Inverse of [`srgb_to_linear`]
+pub fn linear_to_srgb(linear: Vec4) -> Vec4 {
+	let [r, g, b, a] = linear.to_array();
+	Vec4::new(
+		channel_to_srgb(r),
+		channel_to_srgb(g),
+		channel_to_srgb(b),
+		a,
+	)
+}
+
+fn channel_to_linear(value: f32) -> f32 {
+	if value <= 0.04045 {
+		value / 12.92
+	} else {
+		((value + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+fn channel_to_srgb(value: f32) -> f32 {
+	if value <= 0.0031308 {
+		value * 12.92
+	} else {
+		1.055 * value.powf(1.0 / 2.4) - 0.055
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn mid_gray_to_linear() {
+		let linear = srgb_to_linear(Vec4::new(0.5, 0.5, 0.5, 0.5));
+		assert!(
+			linear
+				.truncate()
+				.abs_diff_eq(glam::Vec3::splat(0.214), 1e-3),
+			"{linear}"
+		);
+		assert_eq!(linear.w, 0.5);
+		assert!(linear_to_srgb(linear).abs_diff_eq(Vec4::splat(0.5), 1e-5));
+	}
+}
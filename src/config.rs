@@ -0,0 +1,52 @@
+/// Options that are fixed once the app has started
+#[derive(Clone, Debug, Default)]
+pub struct AppConfig {
+	/// Store spheres in half precision on the gpu, halving the size of the object buffer.
+	///
+	/// Positions, radii, emission and metallic become `f16`, which has 11 significant bits:
+	/// coordinates around 1000 are only accurate to about 0.5 units and the largest value is 65504.
+	/// Colors are stored as 8 bit per channel.
+	pub packed_spheres: bool,
+	pub device_polling: DevicePolling,
+	/// Output color multiplied by alpha and present with a premultiplied alpha mode
+	/// if the surface supports it, for compositing the image over other content
+	pub premultiplied_alpha: bool,
+	/// Most frames the window draws per second, `0` leaves the rate to the present mode
+	pub max_fps: u32,
+	/// How frames are presented to the window, independent of [`AppConfig::max_fps`]
+	pub present_mode: wgpu::PresentMode,
+	pub frame_scheduling: FrameScheduling,
+	/// Inner size of the window in pixels, `None` leaves it to the platform
+	pub window_size: Option<(u32, u32)>,
+	/// Which adapter to prefer when there are several, unless `WGPU_ADAPTER_NAME` picks one
+	pub power_preference: wgpu::PowerPreference,
+	/// Warn about spheres that are coincident with or inside another one whenever the spheres change.
+	/// They can never be hit but still cost intersection tests, usually a mistake in the scene
+	pub validate_spheres: bool,
+	/// Copy every frame into a history texture the shader can reproject into the next frame with the previous camera,
+	/// for temporal effects. Costs a copy of the radiance per frame
+	pub temporal_history: bool,
+}
+
+/// When the renderer polls the device after submitting a frame
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum DevicePolling {
+	/// Only poll while captures are waiting for their readback, otherwise presenting drives the device
+	#[default]
+	OnDemand,
+	/// Poll after every submit, which can even out frame pacing on some platforms
+	EveryFrame,
+}
+
+/// How the window's event loop waits between frames
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FrameScheduling {
+	/// Sleep until the next frame is due, see [`AppConfig::max_fps`], and wake early for input
+	#[default]
+	Timed,
+	/// Never sleep, busy polling the event loop for the lowest input latency at the cost of a cpu core
+	Poll,
+	/// Like `Timed`, but stop drawing once `samples` samples per pixel have been accumulated,
+	/// until input, a resize or a change of the scene
+	OnDemand { samples: u32 },
+}
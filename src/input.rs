@@ -0,0 +1,188 @@
+use glam::Vec2;
+use std::collections::HashMap;
+use winit::event::VirtualKeyCode;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+	/// Write the scene, including the camera, back to its file
+	SaveScene,
+	/// Write only the camera to a separate file
+	SaveCamera,
+	/// Print the camera and settings to stdout, to paste them into a scene file
+	PrintView,
+	/// Save the next frame as a png
+	Capture,
+	/// Turn bloom on or off
+	ToggleBloom,
+	/// Show or hide the [`Crosshair`](crate::settings::Crosshair)
+	ToggleCrosshair,
+	/// Show or hide where the lights are, see [`RenderSettings::light_gizmos`](crate::settings::RenderSettings::light_gizmos)
+	ToggleLightGizmos,
+	/// Switch to the next quality [`Preset`](crate::settings::Preset)
+	CyclePreset,
+	/// Switch to the next [`DebugView`](crate::settings::DebugView)
+	CycleDebugView,
+	/// Tilt the camera counterclockwise
+	RollLeft,
+	/// Tilt the camera clockwise
+	RollRight,
+	/// Level the horizon again
+	ResetRoll,
+	/// Frame the camera on the next sphere of the scene and print it, or the previous one while shift is held
+	FocusSphere,
+	/// Go back to the scene before the last edit in the [`SceneHistory`](crate::scene::SceneHistory), only while ctrl (cmd on macOS) is held
+	Undo,
+	/// Restore the last undone edit, only while ctrl (cmd on macOS) is held
+	Redo,
+	/// Switch to the scene with this index in [`Scenes`](crate::scene::Scenes)
+	SwitchScene(usize),
+}
+
+/// Latest cursor position and the movement while dragging since it was last taken.
+///
+/// Any number of cursor events between two frames collapse into a single drag,
+/// so high polling rate mice don't cause more than one camera update per frame.
+#[derive(Clone, Debug, Default)]
+pub struct Cursor {
+	/// In physical pixels, `None` until the cursor has moved over the window and after it left
+	position: Option<Vec2>,
+	dragging: bool,
+	drag: Vec2,
+}
+
+impl Cursor {
+	pub fn moved(&mut self, position: Vec2) {
+		if let (Some(previous), true) = (self.position, self.dragging) {
+			self.drag += position - previous;
+		}
+		self.position = Some(position);
+	}
+
+	pub fn set_dragging(&mut self, dragging: bool) {
+		self.dragging = dragging;
+	}
+
+	/// Cancels the drag, the button may be released outside of the window where we don't see it.
+	/// Forgetting the position keeps the camera from jumping when the cursor enters somewhere else
+	pub fn left(&mut self) {
+		self.dragging = false;
+		self.position = None;
+	}
+
+	pub fn is_dragging(&self) -> bool {
+		self.dragging
+	}
+
+	pub fn position(&self) -> Option<Vec2> {
+		self.position
+	}
+
+	/// Movement in pixels while dragging since the last call
+	pub fn take_drag(&mut self) -> Vec2 {
+		std::mem::take(&mut self.drag)
+	}
+}
+
+/// Maps actions to the keys that trigger them, every key triggers at most one action
+#[derive(Clone, Debug)]
+pub struct InputBindings {
+	keys: HashMap<Action, VirtualKeyCode>,
+	/// Inverse of `keys`, kept in sync by [`InputBindings::bind`] and [`InputBindings::unbind`]
+	actions: HashMap<VirtualKeyCode, Action>,
+}
+
+impl Default for InputBindings {
+	fn default() -> Self {
+		let defaults = [
+			(Action::SaveScene, VirtualKeyCode::S),
+			(Action::SaveCamera, VirtualKeyCode::C),
+			(Action::PrintView, VirtualKeyCode::V),
+			(Action::Capture, VirtualKeyCode::F12),
+			(Action::CyclePreset, VirtualKeyCode::P),
+			(Action::CycleDebugView, VirtualKeyCode::F),
+			(Action::ToggleBloom, VirtualKeyCode::B),
+			(Action::ToggleCrosshair, VirtualKeyCode::X),
+			(Action::ToggleLightGizmos, VirtualKeyCode::L),
+			(Action::RollLeft, VirtualKeyCode::Q),
+			(Action::RollRight, VirtualKeyCode::E),
+			(Action::ResetRoll, VirtualKeyCode::R),
+			(Action::FocusSphere, VirtualKeyCode::Tab),
+			(Action::Undo, VirtualKeyCode::Z),
+			(Action::Redo, VirtualKeyCode::Y),
+		];
+		let number_keys = [
+			VirtualKeyCode::Key1,
+			VirtualKeyCode::Key2,
+			VirtualKeyCode::Key3,
+			VirtualKeyCode::Key4,
+			VirtualKeyCode::Key5,
+			VirtualKeyCode::Key6,
+			VirtualKeyCode::Key7,
+			VirtualKeyCode::Key8,
+			VirtualKeyCode::Key9,
+		];
+		let mut bindings = Self {
+			keys: HashMap::new(),
+			actions: HashMap::new(),
+		};
+		for (action, key) in defaults {
+			bindings.bind(action, key);
+		}
+		for (index, key) in number_keys.into_iter().enumerate() {
+			bindings.bind(Action::SwitchScene(index), key);
+		}
+		bindings
+	}
+}
+
+impl InputBindings {
+	/// Binds `action` to `key`, replacing its previous key.
+	/// An action that was bound to `key` before is unbound, so the key keeps triggering a single action
+	pub fn bind(&mut self, action: Action, key: VirtualKeyCode) {
+		if let Some(previous_key) = self.keys.insert(action, key) {
+			self.actions.remove(&previous_key);
+		}
+		if let Some(previous_action) = self.actions.insert(key, action) {
+			if previous_action != action {
+				self.keys.remove(&previous_action);
+			}
+		}
+	}
+
+	pub fn unbind(&mut self, action: Action) {
+		if let Some(key) = self.keys.remove(&action) {
+			self.actions.remove(&key);
+		}
+	}
+
+	pub fn key(&self, action: Action) -> Option<VirtualKeyCode> {
+		self.keys.get(&action).copied()
+	}
+
+	/// Action bound to `key`, if any
+	pub fn action(&self, key: VirtualKeyCode) -> Option<Action> {
+		self.actions.get(&key).copied()
+	}
+
+	pub fn iter(&self) -> impl Iterator<Item = (Action, VirtualKeyCode)> + '_ {
+		self.keys.iter().map(|(action, key)| (*action, *key))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn binding_a_taken_key_unbinds_its_action() {
+		let mut bindings = InputBindings::default();
+		bindings.bind(Action::Undo, VirtualKeyCode::S);
+		assert_eq!(bindings.action(VirtualKeyCode::S), Some(Action::Undo));
+		assert_eq!(bindings.key(Action::SaveScene), None);
+		// The previous key of the action is free again
+		assert_eq!(bindings.action(VirtualKeyCode::Z), None);
+
+		bindings.unbind(Action::Undo);
+		assert_eq!(bindings.action(VirtualKeyCode::S), None);
+	}
+}
@@ -0,0 +1,15 @@
+mod bloom;
+pub mod camera;
+pub mod capabilities;
+mod capture;
+pub mod color;
+pub mod config;
+pub mod input;
+mod overlay;
+pub mod points;
+mod profiler;
+pub mod renderer;
+pub mod scene;
+pub mod settings;
+pub mod stats;
+mod textures;
@@ -1,37 +1,109 @@
+use anyhow::Context;
+use clap::Parser;
+use glam::Vec2;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use winit::dpi::PhysicalSize;
-use winit::event::{Event, WindowEvent};
+use winit::event::{
+	ElementState, Event, KeyboardInput, ModifiersState, MouseButton, VirtualKeyCode, WindowEvent,
+};
 use winit::event_loop::{ControlFlow, EventLoop};
-use winit::window::{Window, WindowId};
+use winit::window::{Window, WindowBuilder, WindowId};
 
-mod renderer;
-use renderer::{Renderer, Sphere};
+use rt_bevy::config::{AppConfig, FrameScheduling};
+use rt_bevy::input::{Action, Cursor, InputBindings};
+use rt_bevy::points;
+use rt_bevy::renderer::{Renderer, Sphere, OFFSCREEN_FORMAT};
+use rt_bevy::scene::{self, Scene, SceneHistory, Scenes};
+use rt_bevy::settings::{DebugView, Preset, RenderSettings, SampleController, Toon};
+use rt_bevy::stats::FrameStats;
+
+mod cli;
+
+use cli::{Aov, Args, RunMode};
+
+const DEFAULT_SCENE_PATH: &str = "scene.ron";
+const CAMERA_PATH: &str = "camera.ron";
+/// Camera rotation in radians per pixel the cursor is dragged
+const ORBIT_SPEED: f32 = 0.005;
+/// Radians the camera tilts per key press
+const ROLL_STEP: f32 = std::f32::consts::PI / 36.0;
+const MIN_REDRAW_BACKOFF: Duration = Duration::from_millis(4);
+const MAX_REDRAW_BACKOFF: Duration = Duration::from_millis(250);
 
 struct App {
 	window: Window,
 	surface: wgpu::Surface,
 	config: wgpu::SurfaceConfiguration,
 	renderer: Renderer,
-	scene: Vec<Sphere>,
+	scene: Scene,
+	scene_path: PathBuf,
+	/// Scene files passed on the command line, switched between with the number keys
+	scenes: Scenes,
+	/// Point cloud added to every scene, see [`Scene::points`]
+	points: Vec<Sphere>,
+	/// Edits of `scene` that can be undone
+	history: SceneHistory,
+	/// Whether the camera was dragged since the last snapshot, it is taken once the drag ends
+	camera_dragged: bool,
+	stats: FrameStats,
+	sample_controller: SampleController,
+	/// Last preset applied with [`Action::CyclePreset`]
+	preset: Preset,
+	bindings: InputBindings,
+	cursor: Cursor,
+	/// Delay before the next redraw, grows while acquiring the surface texture keeps timing out
+	redraw_backoff: Duration,
+	next_redraw: Instant,
+	/// Shortest time between the start of two frames, from [`AppConfig::max_fps`]
+	frame_interval: Duration,
+	frame_scheduling: FrameScheduling,
+	/// Set by window events, so even input that doesn't restart accumulation gets one more frame while idle
+	needs_redraw: bool,
+	modifiers: ModifiersState,
+	/// Index into the world spheres of the scene last framed by [`Action::FocusSphere`]
+	focused_sphere: Option<usize>,
 }
 
 impl App {
-	async fn new(event_loop: &EventLoop<()>) -> anyhow::Result<Self> {
-		let window = Window::new(&event_loop)?;
-		let instance = wgpu::Instance::default();
+	async fn new(
+		event_loop: &EventLoop<()>,
+		app_config: AppConfig,
+		scenes: Scenes,
+		points: Vec<Sphere>,
+	) -> anyhow::Result<Self> {
+		let mut window_builder = WindowBuilder::new().with_title("rt_bevy");
+		if let Some((width, height)) = app_config.window_size {
+			window_builder = window_builder.with_inner_size(PhysicalSize::new(width, height));
+		}
+		let window = window_builder.build(event_loop)?;
+		let (instance, backends) = create_instance();
 		let surface = unsafe { instance.create_surface(&window) }?;
-
-		let adapter = instance
-			.request_adapter(&wgpu::RequestAdapterOptions {
-				power_preference: wgpu::PowerPreference::default(),
-				force_fallback_adapter: false,
-				// Request an adapter which can render to our surface
-				compatible_surface: Some(&surface),
-			})
-			.await
-			.expect("Failed to find an appropriate adapter");
+		let adapter = request_adapter(
+			&instance,
+			backends,
+			app_config.power_preference,
+			Some(&surface),
+		)
+		.await?;
 
 		let swapchain_capabilities = surface.get_capabilities(&adapter);
-		let swapchain_format = swapchain_capabilities.formats[0];
+		// The post processing shader encodes to sRGB itself if there is no sRGB format
+		let swapchain_format = swapchain_capabilities
+			.formats
+			.iter()
+			.copied()
+			.find(|format| format.describe().srgb)
+			.unwrap_or(swapchain_capabilities.formats[0]);
+		let alpha_mode = if app_config.premultiplied_alpha
+			&& swapchain_capabilities
+				.alpha_modes
+				.contains(&wgpu::CompositeAlphaMode::PreMultiplied)
+		{
+			wgpu::CompositeAlphaMode::PreMultiplied
+		} else {
+			swapchain_capabilities.alpha_modes[0]
+		};
 
 		let size = window.inner_size();
 		let config = wgpu::SurfaceConfiguration {
@@ -39,61 +111,209 @@ impl App {
 			format: swapchain_format,
 			width: size.width,
 			height: size.height,
-			present_mode: wgpu::PresentMode::Fifo,
-			alpha_mode: swapchain_capabilities.alpha_modes[0],
+			present_mode: app_config.present_mode,
+			alpha_mode,
 			view_formats: vec![],
 		};
 
-		let mut renderer = Renderer::new(adapter, swapchain_format).await?;
-		renderer.update_camera(size.width, size.height);
+		let scene = if points.is_empty() {
+			Scene::example()
+		} else {
+			Scene::from_points(points.clone())
+		};
+		let mut renderer = Renderer::new(
+			adapter,
+			swapchain_format,
+			&scene.world_spheres(),
+			&app_config,
+		)
+		.await?;
+		renderer.resize(size.width, size.height);
+		renderer.update_camera_transform(&scene.camera);
+		renderer.update_settings(&scene.settings);
 		surface.configure(&renderer.device, &config);
 
-		Ok(Self {
+		let app = Self {
 			window,
 			surface,
 			config,
 			renderer,
-			scene: vec![],
-		})
+			history: SceneHistory::default_capacity(&scene)?,
+			camera_dragged: false,
+			scene,
+			scene_path: PathBuf::from(DEFAULT_SCENE_PATH),
+			scenes,
+			points,
+			stats: FrameStats::default(),
+			sample_controller: SampleController::default(),
+			preset: Preset::default(),
+			bindings: InputBindings::default(),
+			cursor: Cursor::default(),
+			redraw_backoff: Duration::ZERO,
+			next_redraw: Instant::now(),
+			frame_interval: match app_config.max_fps {
+				0 => Duration::ZERO,
+				max_fps => Duration::from_secs(1) / max_fps,
+			},
+			frame_scheduling: app_config.frame_scheduling,
+			needs_redraw: true,
+			modifiers: ModifiersState::empty(),
+			focused_sphere: None,
+		};
+		Ok(app)
 	}
 
-	fn with_objects(mut self, mut objects: Vec<Sphere>) -> Self {
-		self.scene.append(&mut objects);
-		self
+	/// Replaces the current scene with the one at `index` in `scenes`, restoring its camera
+	fn switch_scene(&mut self, index: usize) -> anyhow::Result<()> {
+		self.scene = self.scenes.get(index)?.clone();
+		self.scene.points = self.points.clone();
+		// A scene piped into stdin is saved to the default path
+		if let Some(path) = self
+			.scenes
+			.path(index)
+			.filter(|path| path.to_str() != Some(scene::STDIN_PATH))
+		{
+			self.scene_path = path.to_owned();
+		}
+		self.renderer.set_scene(&self.scene);
+		self.history = SceneHistory::default_capacity(&self.scene)?;
+		self.focused_sphere = None;
+		Ok(())
+	}
+
+	/// Frames the camera on the sphere after the focused one, or before it if `backwards`, wrapping around
+	fn focus_sphere(&mut self, backwards: bool) -> anyhow::Result<()> {
+		let spheres = self.scene.world_spheres();
+		let count = spheres.len();
+		if count == 0 {
+			anyhow::bail!("The scene has no spheres to focus");
+		}
+		// The scene may have lost spheres since the last focus
+		let index = match self.focused_sphere.filter(|&index| index < count) {
+			Some(index) if backwards => (index + count - 1) % count,
+			Some(index) => (index + 1) % count,
+			None if backwards => count - 1,
+			None => 0,
+		};
+		self.focused_sphere = Some(index);
+		let sphere = &spheres[index];
+		println!("Sphere {index} of {count}: {sphere:?}");
+		self.scene.camera.frame(sphere.position, sphere.radius);
+		self.renderer.update_camera_transform(&self.scene.camera);
+		Ok(())
+	}
+
+	/// Replaces the scene with one from the history, keeping the path it is saved to
+	fn restore_scene(&mut self, scene: anyhow::Result<Option<Scene>>) -> anyhow::Result<()> {
+		let Some(mut scene) = scene? else {
+			println!("Nothing to restore");
+			return Ok(());
+		};
+		// The history doesn't hold the points, see Scene::points
+		scene.points = std::mem::take(&mut self.scene.points);
+		self.scene = scene;
+		self.renderer.set_scene(&self.scene);
+		Ok(())
+	}
+
+	/// Shows the frame rate, sample count and scene size in the corner of the next frame
+	fn draw_stats(&mut self) {
+		let stats = self.renderer.stats();
+		let average_ms = self.stats.average_ms();
+		let mut lines = vec![
+			format!(
+				"{:.0} fps ({average_ms:.1} ms)",
+				1000.0 / average_ms.max(0.001)
+			),
+			format!("{} samples", self.renderer.sample_count()),
+			format!(
+				"{} spheres, {} bytes on gpu",
+				stats.sphere_count,
+				stats.total_buffer_size()
+			),
+		];
+		// Only filled in if the gpu supports timestamp queries
+		lines.extend(
+			self.stats
+				.pass_ms
+				.iter()
+				.map(|(pass, ms)| format!("  {pass}: {ms:.2} ms")),
+		);
+		self.renderer.draw_text(&lines);
 	}
 
 	fn run(mut self, event_loop: EventLoop<()>) -> anyhow::Result<()> {
 		event_loop.run(move |event, _, control_flow| {
-			control_flow.set_wait();
+			match self.frame_scheduling {
+				FrameScheduling::Poll => control_flow.set_poll(),
+				FrameScheduling::Timed | FrameScheduling::OnDemand { .. } => {
+					control_flow.set_wait()
+				}
+			}
 
 			match event {
 				Event::WindowEvent { event, window_id } => {
+					self.needs_redraw = true;
 					self.handle_window_event(window_id, event, control_flow)
 				}
 				Event::RedrawRequested(window_id) => {
 					if self.window.id() != window_id {
 						return;
 					}
-					let Err(err) = self.redraw() else  {
+					// Measured from the start, so the time spent rendering counts towards the interval
+					let frame_start = Instant::now();
+					let Err(err) = self.redraw() else {
+						self.redraw_backoff = Duration::ZERO;
+						self.next_redraw = frame_start + self.frame_interval;
 						return;
 					};
 					match err {
 						wgpu::SurfaceError::OutOfMemory => control_flow.set_exit(),
 						// Reconfigure the surface if lost
 						wgpu::SurfaceError::Lost => self.resize(self.window.inner_size()),
-						// Outdated, Timeout errors should be resolved by the next frame
+						// Redraw less often while timeouts keep happening
+						wgpu::SurfaceError::Timeout => self.back_off_redraw(),
+						// Outdated errors should be resolved by the next frame
 						err => eprintln!("{err}"),
 					};
 				}
 				Event::MainEventsCleared => {
 					// RedrawRequested will only trigger once, unless we manually request it.
-					self.window.request_redraw();
+					if self.is_idle() {
+						// Wait for the next event without a timeout
+					} else if Instant::now() >= self.next_redraw {
+						self.window.request_redraw();
+					} else if self.frame_scheduling != FrameScheduling::Poll {
+						control_flow.set_wait_until(self.next_redraw);
+					}
 				}
 				_ => {}
 			}
 		});
 	}
 
+	/// Whether the image is done and nothing needs another frame, see [`FrameScheduling::OnDemand`]
+	fn is_idle(&self) -> bool {
+		let FrameScheduling::OnDemand { samples } = self.frame_scheduling else {
+			return false;
+		};
+		!self.needs_redraw
+			&& self.renderer.is_converged(samples)
+			&& !self.cursor.is_dragging()
+			&& !self.camera_dragged
+			&& !self.renderer.has_pending_captures()
+	}
+
+	fn back_off_redraw(&mut self) {
+		self.redraw_backoff =
+			(self.redraw_backoff * 2).clamp(MIN_REDRAW_BACKOFF, MAX_REDRAW_BACKOFF);
+		self.next_redraw = Instant::now() + self.redraw_backoff;
+		eprintln!(
+			"Timed out acquiring the surface texture, retrying in {:?}",
+			self.redraw_backoff
+		);
+	}
+
 	fn handle_window_event(
 		&mut self,
 		_window_id: WindowId,
@@ -103,14 +323,162 @@ impl App {
 		match event {
 			WindowEvent::CloseRequested => control_flow.set_exit(),
 			WindowEvent::Resized(size) => self.resize(size),
+			WindowEvent::CursorMoved { position, .. } => self
+				.cursor
+				.moved(Vec2::new(position.x as f32, position.y as f32)),
+			WindowEvent::CursorLeft { .. } => self.cursor.left(),
+			WindowEvent::ModifiersChanged(modifiers) => self.modifiers = modifiers,
+			WindowEvent::MouseInput {
+				state,
+				button: MouseButton::Left,
+				..
+			} => self.cursor.set_dragging(state == ElementState::Pressed),
+			WindowEvent::KeyboardInput {
+				input:
+					KeyboardInput {
+						state: ElementState::Pressed,
+						virtual_keycode: Some(key),
+						..
+					},
+				..
+			} => self.handle_key(key),
 			_ => {}
 		}
 	}
 
+	fn handle_key(&mut self, key: VirtualKeyCode) {
+		let Some(action) = self.bindings.action(key) else {
+			return;
+		};
+		// Undo and redo are shortcuts, so a stray keypress doesn't throw away an edit
+		let shortcut_held = if cfg!(target_os = "macos") {
+			self.modifiers.logo()
+		} else {
+			self.modifiers.ctrl()
+		};
+		if matches!(action, Action::Undo | Action::Redo) && !shortcut_held {
+			return;
+		}
+		let edits_scene = matches!(
+			action,
+			Action::ToggleBloom
+				| Action::ToggleCrosshair
+				| Action::ToggleLightGizmos
+				| Action::CyclePreset
+				| Action::CycleDebugView
+				| Action::RollLeft
+				| Action::RollRight
+				| Action::ResetRoll
+				| Action::FocusSphere
+		);
+		let result = match action {
+			Action::SaveScene => scene::save_scene(&self.scene, &self.scene_path),
+			Action::SaveCamera => scene::save_camera(&self.scene.camera, CAMERA_PATH),
+			Action::PrintView => scene::view_to_ron(&self.scene.camera, &self.scene.settings)
+				.map(|ron| println!("{ron}")),
+			Action::Capture => {
+				let timestamp = SystemTime::now()
+					.duration_since(UNIX_EPOCH)
+					.unwrap_or_default()
+					.as_millis();
+				self.renderer.capture(format!("capture_{timestamp}.png"));
+				Ok(())
+			}
+			Action::ToggleBloom => {
+				self.scene.settings.bloom = !self.scene.settings.bloom;
+				self.renderer.update_settings(&self.scene.settings);
+				Ok(())
+			}
+			Action::ToggleCrosshair => {
+				let crosshair = &mut self.scene.settings.crosshair;
+				crosshair.enabled = !crosshair.enabled;
+				self.renderer.update_settings(&self.scene.settings);
+				Ok(())
+			}
+			Action::ToggleLightGizmos => {
+				let settings = &mut self.scene.settings;
+				settings.light_gizmos = !settings.light_gizmos;
+				self.renderer.update_settings(settings);
+				Ok(())
+			}
+			Action::CyclePreset => {
+				self.preset = self.preset.next();
+				self.scene.settings.apply_preset(self.preset);
+				self.renderer.update_settings(&self.scene.settings);
+				println!("Switched to the {:?} preset", self.preset);
+				Ok(())
+			}
+			Action::CycleDebugView => {
+				let settings = &mut self.scene.settings;
+				settings.debug_view = settings.debug_view.next();
+				self.renderer.update_settings(settings);
+				println!("Switched to the {:?} view", settings.debug_view);
+				Ok(())
+			}
+			Action::RollLeft | Action::RollRight | Action::ResetRoll => {
+				let camera = &mut self.scene.camera;
+				match action {
+					Action::RollLeft => camera.roll_by(-ROLL_STEP),
+					Action::RollRight => camera.roll_by(ROLL_STEP),
+					_ => camera.roll = 0.0,
+				}
+				self.renderer.update_camera_transform(camera);
+				Ok(())
+			}
+			Action::FocusSphere => self.focus_sphere(self.modifiers.shift()),
+			Action::Undo => {
+				let scene = self.history.undo();
+				self.restore_scene(scene)
+			}
+			Action::Redo => {
+				let scene = self.history.redo();
+				self.restore_scene(scene)
+			}
+			Action::SwitchScene(index) => self.switch_scene(index),
+		};
+		let result = result.and_then(|()| {
+			if edits_scene {
+				self.history.commit(&self.scene)
+			} else {
+				Ok(())
+			}
+		});
+		if let Err(err) = result {
+			eprintln!("{err}");
+		}
+	}
+
 	fn redraw(&mut self) -> anyhow::Result<(), wgpu::SurfaceError> {
+		self.needs_redraw = false;
+		self.stats.tick();
+		// Everything dragged since the last frame results in one camera update
+		let drag = self.cursor.take_drag();
+		if drag != Vec2::ZERO {
+			self.scene
+				.camera
+				.orbit(-drag.x * ORBIT_SPEED, -drag.y * ORBIT_SPEED);
+			self.renderer.update_camera_transform(&self.scene.camera);
+			self.camera_dragged = true;
+		}
+		// A whole drag is undone at once
+		if self.camera_dragged && !self.cursor.is_dragging() {
+			self.camera_dragged = false;
+			if let Err(err) = self.history.commit(&self.scene) {
+				eprintln!("{err}");
+			}
+		}
+		if self
+			.sample_controller
+			.update(&mut self.scene.settings, &self.stats)
+		{
+			self.renderer.update_settings(&self.scene.settings);
+		}
+
 		let surface_texture = self.surface.get_current_texture()?;
+		self.draw_stats();
 		self.renderer.render(&surface_texture.texture);
 		surface_texture.present();
+		self.stats.pass_ms.clone_from(self.renderer.pass_timings());
 		Ok(())
 	}
 
@@ -119,26 +487,284 @@ impl App {
 		self.config.width = width;
 		self.config.height = height;
 		self.surface.configure(&self.renderer.device, &self.config);
-		// Update the camera data sent to the gpu
-		self.renderer.update_camera(width, height);
+		// Recreate the size dependent textures and update the camera data sent to the gpu
+		self.renderer.resize(width, height);
 		// On macos the window needs to be redrawn manually after resizing
 		self.window.request_redraw();
 	}
 }
 
+fn create_instance() -> (wgpu::Instance, wgpu::Backends) {
+	// WGPU_BACKEND=vulkan|dx12|metal|gl limits the backends that are tried
+	let backends = wgpu::util::backend_bits_from_env().unwrap_or_else(wgpu::Backends::all);
+	let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+		backends,
+		..Default::default()
+	});
+	(instance, backends)
+}
+
+/// Adapter picked by [`adapter_from_env`] or the default one, which can render to `surface` if given
+async fn request_adapter(
+	instance: &wgpu::Instance,
+	backends: wgpu::Backends,
+	power_preference: wgpu::PowerPreference,
+	surface: Option<&wgpu::Surface>,
+) -> anyhow::Result<wgpu::Adapter> {
+	let adapter = match adapter_from_env(instance, backends, surface) {
+		Some(adapter) => adapter,
+		None => instance
+			.request_adapter(&wgpu::RequestAdapterOptions {
+				power_preference,
+				force_fallback_adapter: false,
+				// Request an adapter which can render to our surface
+				compatible_surface: surface,
+			})
+			.await
+			.context("Failed to find an appropriate adapter")?,
+	};
+	let info = adapter.get_info();
+	log::info!("Using adapter {} ({:?})", info.name, info.backend);
+	Ok(adapter)
+}
+
+/// Adapter whose name contains WGPU_ADAPTER_NAME (case insensitive) and that can render to `surface`
+fn adapter_from_env(
+	instance: &wgpu::Instance,
+	backends: wgpu::Backends,
+	surface: Option<&wgpu::Surface>,
+) -> Option<wgpu::Adapter> {
+	let name = std::env::var("WGPU_ADAPTER_NAME").ok()?.to_lowercase();
+	let adapter = instance.enumerate_adapters(backends).find(|adapter| {
+		adapter.get_info().name.to_lowercase().contains(&name)
+			&& surface.is_none_or(|surface| adapter.is_surface_supported(surface))
+	});
+	if adapter.is_none() {
+		log::warn!("No adapter matching WGPU_ADAPTER_NAME={name}, falling back to the default");
+	}
+	adapter
+}
+
 #[pollster::main]
 async fn main() -> anyhow::Result<()> {
 	env_logger::init();
 
+	let args = Args::parse();
+	let mut scenes = Scenes::new(&args.scenes);
+
+	let points = match &args.points {
+		Some(path) => {
+			let points = points::load_points(path, args.point_radius)?;
+			println!("Loaded {} points from {}", points.len(), path.display());
+			points
+		}
+		None => Vec::new(),
+	};
+
+	let mode = args.run_mode();
+	if mode != RunMode::Interactive {
+		let scene = if !scenes.is_empty() {
+			let mut scene = scenes.get(0)?.clone();
+			scene.points = points;
+			scene
+		} else if !points.is_empty() {
+			Scene::from_points(points)
+		} else {
+			Scene::example()
+		};
+		return render_offline(scene, &args.app_config(), args.headless_size(), mode).await;
+	}
+
 	let event_loop = EventLoop::new();
-	App::new(&event_loop)
-		.await?
-		// .with_objects(vec![Sphere {
-		// 	radius: 1.0,
-		// 	position: Vec3::ZERO,
-		// color: Vec3::new(0.6, 0.4, 0.1),
-		// }])
-		.run(event_loop)?;
+	let load_first_scene = !scenes.is_empty();
+	let mut app = App::new(&event_loop, args.app_config(), scenes, points).await?;
+	if load_first_scene {
+		app.switch_scene(0)?;
+	}
+	app.run(event_loop)?;
+
+	Ok(())
+}
+
+/// Renders `scene` without a window and saves or times the frames, see [`RunMode`]
+async fn render_offline(
+	mut scene: Scene,
+	config: &AppConfig,
+	(width, height): (u32, u32),
+	mode: RunMode,
+) -> anyhow::Result<()> {
+	// Identifies the image a headless render produces, stored next to it for --skip-unchanged
+	let render_key = match &mode {
+		RunMode::Headless {
+			frames,
+			output,
+			skip_unchanged,
+			aovs,
+			..
+		} => {
+			let hash_path = cli::hash_path(output);
+			let passes: String = aovs.iter().map(|aov| format!(" {aov:?}")).collect();
+			let key = format!(
+				"{:016x} {width}x{height} {frames}{passes}\n",
+				scene.scene_hash()
+			);
+			if *skip_unchanged
+				&& output.exists()
+				&& aovs.iter().all(|aov| aov.path(output).exists())
+				&& std::fs::read_to_string(&hash_path).is_ok_and(|previous| previous == key)
+			{
+				println!("{} is up to date, skipping the render", output.display());
+				return Ok(());
+			}
+			Some((hash_path, key))
+		}
+		_ => None,
+	};
+
+	let (instance, backends) = create_instance();
+	let adapter = request_adapter(&instance, backends, config.power_preference, None).await?;
+	let mut renderer =
+		Renderer::new(adapter, OFFSCREEN_FORMAT, &scene.world_spheres(), config).await?;
+	// Tiled images may be too large for the gpu, the tiles get their own size
+	if !matches!(
+		mode,
+		RunMode::Headless {
+			tile_size: Some(_),
+			..
+		}
+	) {
+		renderer.resize(width, height);
+	}
+	renderer.set_scene(&scene);
+
+	// Exr files get the linear image before post processing
+	let capture = |renderer: &mut Renderer, path: PathBuf| {
+		if path.extension().is_some_and(|extension| extension == "exr") {
+			renderer.capture_hdr(path);
+		} else {
+			renderer.capture(path);
+		}
+	};
+	match mode {
+		RunMode::Interactive => unreachable!("Interactive mode opens a window"),
+		RunMode::Headless {
+			frames,
+			output,
+			tile_size: Some(tile_size),
+			..
+		} => {
+			if output
+				.extension()
+				.is_some_and(|extension| extension == "exr")
+			{
+				anyhow::bail!("Tiled rendering only supports png output");
+			}
+			let mut image = image::RgbaImage::new(width, height);
+			for y in (0..height).step_by(tile_size as usize) {
+				for x in (0..width).step_by(tile_size as usize) {
+					let tile_width = tile_size.min(width - x);
+					let tile_height = tile_size.min(height - y);
+					let tile = renderer.render_tile(
+						(width, height),
+						(x, y, tile_width, tile_height),
+						frames,
+					)?;
+					image::imageops::replace(&mut image, &tile, x as i64, y as i64);
+				}
+			}
+			image
+				.save(&output)
+				.with_context(|| format!("Failed to save {}", output.display()))?;
+			println!("Saved capture to {}", output.display());
+		}
+		RunMode::Headless {
+			frames,
+			output,
+			tile_size: None,
+			aovs,
+			..
+		} => {
+			// The capture renders the last frame
+			for _ in 1..frames {
+				renderer.render_offscreen();
+			}
+			capture(&mut renderer, output.clone());
+
+			for aov in aovs {
+				let path = aov.path(&output);
+				let debug_view = match aov {
+					// Taken from the frame that was just captured
+					Aov::Depth => {
+						renderer.capture_depth(path)?;
+						continue;
+					}
+					Aov::Normal => DebugView::Normal,
+					Aov::Albedo => DebugView::Albedo,
+				};
+				// The passes hold plain surface values, without post processing like the glow or outlines
+				let settings = RenderSettings {
+					debug_view,
+					bloom: false,
+					toon: Toon::default(),
+					..scene.settings
+				};
+				renderer.update_settings(&settings);
+				for _ in 1..frames {
+					renderer.render_offscreen();
+				}
+				capture(&mut renderer, path);
+			}
+		}
+		RunMode::Turntable { frames, output } => {
+			let start_yaw = scene.camera.yaw;
+			for step in 0..frames {
+				scene.camera.yaw = start_yaw + std::f32::consts::TAU * step as f32 / frames as f32;
+				renderer.update_camera_transform(&scene.camera);
+				capture(&mut renderer, cli::numbered_path(&output, step));
+			}
+		}
+		RunMode::Bench { frames } => {
+			// Moving the camera restarts accumulation every frame, like an animated scene would
+			let start_yaw = scene.camera.yaw;
+			let mut frame_times = Vec::with_capacity(frames as usize);
+			let bench_start = Instant::now();
+			for step in 0..frames {
+				let frame_start = Instant::now();
+				scene.camera.yaw = start_yaw + std::f32::consts::TAU * step as f32 / frames as f32;
+				renderer.update_camera_transform(&scene.camera);
+				renderer.render_offscreen();
+				renderer.wait_for_gpu();
+				frame_times.push(frame_start.elapsed());
+			}
+			let total = bench_start.elapsed();
+
+			frame_times.sort_unstable();
+			let percentile = |p: f32| {
+				let index = ((frame_times.len() - 1) as f32 * p).round() as usize;
+				frame_times[index].as_secs_f32() * 1000.0
+			};
+			let average = total.as_secs_f32() * 1000.0 / frames as f32;
+			println!(
+				"{frames} frames of {} spheres at {width}x{height} in {:.2}s ({:.1} fps)",
+				renderer.stats().sphere_count,
+				total.as_secs_f32(),
+				frames as f32 / total.as_secs_f32(),
+			);
+			println!(
+				"frame time avg {average:.2}ms, p50 {:.2}ms, p95 {:.2}ms, p99 {:.2}ms, max {:.2}ms",
+				percentile(0.5),
+				percentile(0.95),
+				percentile(0.99),
+				percentile(1.0),
+			);
+		}
+	}
+	renderer.wait_for_captures();
+
+	if let Some((hash_path, key)) = render_key {
+		std::fs::write(&hash_path, key)
+			.with_context(|| format!("Failed to write {}", hash_path.display()))?;
+	}
 
 	Ok(())
 }
@@ -0,0 +1,399 @@
+use glam::{Vec2, Vec3};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use wgpu::util::DeviceExt;
+
+use crate::settings::Crosshair;
+
+/// Coverage of the printable ascii characters in rows of 16, rendered from DejaVu Sans Mono at 13 pixels
+const FONT_ATLAS: &[u8] = include_bytes!("font.png");
+const ATLAS_COLUMNS: u32 = 16;
+const FIRST_CHARACTER: char = ' ';
+const LAST_CHARACTER: char = '~';
+/// Drawn instead of characters missing from the atlas
+const REPLACEMENT_CHARACTER: char = '?';
+/// Size of a character in the atlas and on screen in pixels
+const GLYPH_SIZE: Vec2 = Vec2::new(8.0, 14.0);
+/// Distance between the text and the top left corner in pixels
+const MARGIN: f32 = 8.0;
+/// Offset of the shadow that keeps the text readable on bright backgrounds
+const SHADOW_OFFSET: Vec2 = Vec2::ONE;
+/// Width of the crosshair arms in pixels, even so they cover whole pixels around the center
+const CROSSHAIR_THICKNESS: f32 = 2.0;
+
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct ScreenUniform {
+	size: Vec2,
+	/// `1` if the target doesn't convert to sRGB by itself, like in the post processing shader
+	encode_srgb: u32,
+	_padding: u32,
+}
+
+// Layout of `Screen` in overlay.wgsl
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(ScreenUniform, encode_srgb) == 8);
+	assert!(size_of::<ScreenUniform>() == 16);
+};
+
+/// Instance data of one drawn rectangle, a character, an arm of the crosshair or a dot of a segment
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct Quad {
+	/// Top left corner on screen in pixels
+	position: Vec2,
+	/// Size on screen in pixels, characters have the size of their glyph
+	size: Vec2,
+	/// Top left corner of the glyph in the atlas in texels, negative for a solid rectangle
+	glyph: Vec2,
+	/// Linear color, black for shadows
+	color: Vec3,
+}
+
+impl Quad {
+	fn character(position: Vec2, character: char, color: Vec3) -> Self {
+		let character = if (FIRST_CHARACTER..=LAST_CHARACTER).contains(&character) {
+			character
+		} else {
+			REPLACEMENT_CHARACTER
+		};
+		let index = character as u32 - FIRST_CHARACTER as u32;
+		let cell = Vec2::new(
+			(index % ATLAS_COLUMNS) as f32,
+			(index / ATLAS_COLUMNS) as f32,
+		);
+		Self {
+			position,
+			size: GLYPH_SIZE,
+			glyph: cell * GLYPH_SIZE,
+			color,
+		}
+	}
+
+	fn solid(position: Vec2, size: Vec2, color: Vec3) -> Self {
+		Self {
+			position,
+			size,
+			glyph: Vec2::NEG_ONE,
+			color,
+		}
+	}
+
+	fn descriptor<'a>() -> wgpu::VertexBufferLayout<'a> {
+		wgpu::VertexBufferLayout {
+			array_stride: std::mem::size_of::<Quad>() as wgpu::BufferAddress,
+			step_mode: wgpu::VertexStepMode::Instance,
+			attributes: &[
+				wgpu::VertexAttribute {
+					offset: 0,
+					shader_location: 0,
+					format: wgpu::VertexFormat::Float32x2,
+				},
+				wgpu::VertexAttribute {
+					offset: std::mem::size_of::<Vec2>() as wgpu::BufferAddress,
+					shader_location: 1,
+					format: wgpu::VertexFormat::Float32x2,
+				},
+				wgpu::VertexAttribute {
+					offset: 2 * std::mem::size_of::<Vec2>() as wgpu::BufferAddress,
+					shader_location: 2,
+					format: wgpu::VertexFormat::Float32x2,
+				},
+				wgpu::VertexAttribute {
+					offset: 3 * std::mem::size_of::<Vec2>() as wgpu::BufferAddress,
+					shader_location: 3,
+					format: wgpu::VertexFormat::Float32x3,
+				},
+			],
+		}
+	}
+}
+
+/// Draws lines of text in the top left corner, the crosshair and line segments, after post processing
+pub(crate) struct Overlay {
+	layout: wgpu::PipelineLayout,
+	shader: wgpu::ShaderModule,
+	/// One pipeline per texture format that has been drawn into
+	pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+	bind_group: wgpu::BindGroup,
+	screen_buffer: wgpu::Buffer,
+	/// Grows to fit the quads of the longest text drawn so far
+	quad_buffer: wgpu::Buffer,
+	/// Drawn and cleared by the next call to [`Overlay::render`]
+	lines: Vec<String>,
+	/// Drawn and cleared by the next call to [`Overlay::render`]
+	crosshair: Option<Crosshair>,
+	/// Start, end, thickness and color, drawn and cleared by the next call to [`Overlay::render`]
+	segments: Vec<(Vec2, Vec2, f32, Vec3)>,
+}
+
+impl Overlay {
+	pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> anyhow::Result<Self> {
+		let atlas = image::load_from_memory(FONT_ATLAS)?.into_luma8();
+		let size = wgpu::Extent3d {
+			width: atlas.width(),
+			height: atlas.height(),
+			depth_or_array_layers: 1,
+		};
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Font Atlas"),
+			size,
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: wgpu::TextureFormat::R8Unorm,
+			usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+			view_formats: &[],
+		});
+		queue.write_texture(
+			texture.as_image_copy(),
+			&atlas,
+			wgpu::ImageDataLayout {
+				offset: 0,
+				bytes_per_row: std::num::NonZeroU32::new(atlas.width()),
+				rows_per_image: None,
+			},
+			size,
+		);
+		let atlas_view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+		let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Overlay Screen Buffer"),
+			contents: bytemuck::cast_slice(&[ScreenUniform {
+				size: Vec2::ONE,
+				encode_srgb: 0,
+				_padding: 0,
+			}]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+			label: Some("Overlay Bind Group Layout"),
+			entries: &[
+				wgpu::BindGroupLayoutEntry {
+					binding: 0,
+					visibility: wgpu::ShaderStages::FRAGMENT,
+					ty: wgpu::BindingType::Texture {
+						sample_type: wgpu::TextureSampleType::Float { filterable: false },
+						view_dimension: wgpu::TextureViewDimension::D2,
+						multisampled: false,
+					},
+					count: None,
+				},
+				wgpu::BindGroupLayoutEntry {
+					binding: 1,
+					visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+					ty: wgpu::BindingType::Buffer {
+						ty: wgpu::BufferBindingType::Uniform,
+						has_dynamic_offset: false,
+						min_binding_size: None,
+					},
+					count: None,
+				},
+			],
+		});
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Overlay Bind Group"),
+			layout: &bind_group_layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&atlas_view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: screen_buffer.as_entire_binding(),
+				},
+			],
+		});
+
+		let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+			label: Some("Overlay Shader"),
+			source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!("overlay.wgsl"))),
+		});
+		let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Overlay Pipeline Layout"),
+			bind_group_layouts: &[&bind_group_layout],
+			push_constant_ranges: &[],
+		});
+
+		Ok(Self {
+			layout,
+			shader,
+			pipelines: HashMap::new(),
+			bind_group,
+			screen_buffer,
+			quad_buffer: create_quad_buffer(device, 0),
+			lines: Vec::new(),
+			crosshair: None,
+			segments: Vec::new(),
+		})
+	}
+
+	/// Adds `lines` below the ones pushed since the last frame
+	pub(crate) fn push_lines(&mut self, lines: &[String]) {
+		self.lines.extend_from_slice(lines);
+	}
+
+	/// Draws `crosshair` over the center of the next frame
+	pub(crate) fn push_crosshair(&mut self, crosshair: Crosshair) {
+		self.crosshair = Some(crosshair);
+	}
+
+	/// Draws a line from `from` to `to` in pixels from the top left corner over the next frame,
+	/// a dot if they are the same
+	pub(crate) fn push_segment(&mut self, from: Vec2, to: Vec2, thickness: f32, color: Vec3) {
+		self.segments.push((from, to, thickness, color));
+	}
+
+	/// Draws the pushed lines, crosshair and segments over `view`, which is `size` pixels large
+	pub(crate) fn render(
+		&mut self,
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		encoder: &mut wgpu::CommandEncoder,
+		view: &wgpu::TextureView,
+		format: wgpu::TextureFormat,
+		(width, height): (u32, u32),
+	) {
+		let text: Vec<_> = self
+			.lines
+			.drain(..)
+			.enumerate()
+			.flat_map(|(row, line)| {
+				line.chars()
+					.enumerate()
+					.filter(|(_, character)| *character != ' ')
+					.map(|(column, character)| {
+						let cell = Vec2::new(column as f32, row as f32);
+						(Vec2::splat(MARGIN) + cell * GLYPH_SIZE, character)
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect();
+		// Centered on a pixel corner, so the arms cover whole pixels
+		let center = Vec2::new((width / 2) as f32, (height / 2) as f32);
+		let arms: Vec<_> = self
+			.crosshair
+			.take()
+			.into_iter()
+			.flat_map(|crosshair| {
+				let length = crosshair.size.round().max(1.0);
+				let horizontal = Vec2::new(2.0 * length, CROSSHAIR_THICKNESS);
+				let vertical = Vec2::new(CROSSHAIR_THICKNESS, 2.0 * length);
+				[
+					(center - horizontal * 0.5, horizontal, crosshair.color),
+					(center - vertical * 0.5, vertical, crosshair.color),
+				]
+			})
+			.collect();
+		// Segments are made of square dots that overlap by half
+		let dots = self
+			.segments
+			.drain(..)
+			.flat_map(|(from, to, thickness, color)| {
+				let count = ((to - from).length() / thickness * 2.0).ceil() as usize;
+				(0..=count).map(move |dot| {
+					let center = from.lerp(to, dot as f32 / count.max(1) as f32);
+					let size = Vec2::splat(thickness);
+					(center - size * 0.5, size, color)
+				})
+			});
+		let arms: Vec<_> = arms.into_iter().chain(dots).collect();
+
+		// Shadows first, so the text, crosshair and segments are drawn over them
+		let shadows = text
+			.iter()
+			.map(|&(position, character)| {
+				Quad::character(position + SHADOW_OFFSET, character, Vec3::ZERO)
+			})
+			.chain(arms.iter().map(|&(position, size, _)| {
+				Quad::solid(position + SHADOW_OFFSET, size, Vec3::ZERO)
+			}));
+		let quads: Vec<_> = shadows
+			.chain(
+				text.iter()
+					.map(|&(position, character)| Quad::character(position, character, Vec3::ONE)),
+			)
+			.chain(
+				arms.iter()
+					.map(|&(position, size, color)| Quad::solid(position, size, color)),
+			)
+			.collect();
+		if quads.is_empty() {
+			return;
+		}
+
+		let size = (quads.len() * std::mem::size_of::<Quad>()) as wgpu::BufferAddress;
+		if self.quad_buffer.size() < size {
+			self.quad_buffer = create_quad_buffer(device, size.next_power_of_two());
+		}
+		queue.write_buffer(&self.quad_buffer, 0, bytemuck::cast_slice(&quads));
+		queue.write_buffer(
+			&self.screen_buffer,
+			0,
+			bytemuck::cast_slice(&[ScreenUniform {
+				size: Vec2::new(width.max(1) as f32, height.max(1) as f32),
+				encode_srgb: (!format.describe().srgb).into(),
+				_padding: 0,
+			}]),
+		);
+
+		let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+			device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+				label: Some(&format!("Overlay Pipeline {format:?}")),
+				layout: Some(&self.layout),
+				vertex: wgpu::VertexState {
+					module: &self.shader,
+					entry_point: "vs_main",
+					buffers: &[Quad::descriptor()],
+				},
+				fragment: Some(wgpu::FragmentState {
+					module: &self.shader,
+					entry_point: "fs_main",
+					targets: &[Some(wgpu::ColorTargetState {
+						format,
+						blend: Some(wgpu::BlendState::PREMULTIPLIED_ALPHA_BLENDING),
+						write_mask: wgpu::ColorWrites::ALL,
+					})],
+				}),
+				primitive: wgpu::PrimitiveState {
+					topology: wgpu::PrimitiveTopology::TriangleStrip,
+					..Default::default()
+				},
+				depth_stencil: None,
+				multisample: wgpu::MultisampleState::default(),
+				multiview: None,
+			})
+		});
+
+		let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+			label: Some("Overlay Pass"),
+			color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+				view,
+				resolve_target: None,
+				ops: wgpu::Operations {
+					load: wgpu::LoadOp::Load,
+					store: true,
+				},
+			})],
+			depth_stencil_attachment: None,
+		});
+		pass.set_pipeline(pipeline);
+		pass.set_bind_group(0, &self.bind_group, &[]);
+		pass.set_vertex_buffer(0, self.quad_buffer.slice(..size));
+		pass.draw(0..4, 0..quads.len() as u32);
+	}
+}
+
+fn create_quad_buffer(device: &wgpu::Device, size: wgpu::BufferAddress) -> wgpu::Buffer {
+	device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Overlay Quad Buffer"),
+		// Zero sized buffers can't be bound
+		size: size.max(std::mem::size_of::<Quad>() as wgpu::BufferAddress),
+		usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+		mapped_at_creation: false,
+	})
+}
@@ -0,0 +1,63 @@
+// Text and the crosshair drawn over the final image, one instanced quad per character or crosshair arm
+
+struct Screen {
+  // size of the target in pixels
+  size: vec2<f32>,
+  // 1 if the target doesn't convert to sRGB by itself
+  encode_srgb: u32,
+}
+
+// coverage of every printable ascii character, see font.png
+@group(0)
+@binding(0)
+var atlas: texture_2d<f32>;
+@group(0)
+@binding(1)
+var<uniform> screen: Screen;
+
+struct Quad {
+  // top left corner on screen in pixels
+  @location(0) position: vec2<f32>,
+  // size on screen in pixels
+  @location(1) size: vec2<f32>,
+  // top left corner of the glyph in the atlas in texels, negative for a solid rectangle
+  @location(2) glyph: vec2<f32>,
+  // linear color, black for shadows
+  @location(3) color: vec3<f32>,
+}
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(0) texel: vec2<f32>,
+  @location(1) color: vec3<f32>,
+  // 1 if the atlas is sampled, 0 for solid rectangles
+  @location(2) textured: f32,
+};
+
+// Quad drawn as a triangle strip of four vertices
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32, quad: Quad) -> VertexOutput {
+    let corner = vec2(f32(index & 1u), f32(index >> 1u)) * quad.size;
+    let pixel = quad.position + corner;
+    var out: VertexOutput;
+    out.position = vec4(pixel / screen.size * vec2(2.0, -2.0) + vec2(-1.0, 1.0), 0.0, 1.0);
+    out.texel = quad.glyph + corner;
+    out.color = quad.color;
+    out.textured = select(1.0, 0.0, quad.glyph.x < 0.0);
+    return out;
+}
+
+// Premultiplied, so it can be blended onto both straight and premultiplied alpha targets
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var coverage = 1.0;
+    if in.textured > 0.5 {
+        coverage = textureLoad(atlas, vec2<i32>(in.texel), 0).r;
+    }
+    var color = in.color;
+    if screen.encode_srgb != 0u {
+        let linear = clamp(color, vec3(0.0), vec3(1.0));
+        color = select(1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, linear * 12.92, linear <= vec3(0.0031308));
+    }
+    return vec4(color * coverage, coverage);
+}
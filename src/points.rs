@@ -0,0 +1,463 @@
+use anyhow::Context;
+use glam::{Vec3, Vec4};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use crate::renderer::{Sphere, MAX_SPHERES};
+
+/// Color of points that don't have one in the file
+const DEFAULT_COLOR: Vec4 = Vec4::new(0.8, 0.8, 0.8, 1.0);
+
+/// A sphere for every point of the point cloud at `path`, with its radius and sRGB color if the file has them.
+///
+/// `.ply` files can be ascii or binary, their vertices need `x`, `y` and `z` properties and can have `radius`
+/// and `red`, `green` and `blue`. Anything else is read as csv, with a header naming the same columns
+/// or the columns `x y z`, `x y z radius`, `x y z r g b` or `x y z radius r g b` separated by commas or whitespace.
+/// Csv colors go from 0 to 1, or from 0 to 255 if any channel in the file is larger than 1.
+///
+/// Fails for clouds with more than [`MAX_SPHERES`] points. Devices with small storage buffer bindings
+/// can hold fewer, the renderer drops the rest with a warning then. see [`AppConfig::packed_spheres`](crate::config::AppConfig::packed_spheres) to fit more
+pub fn load_points(path: impl AsRef<Path>, radius: f32) -> anyhow::Result<Vec<Sphere>> {
+	let path = path.as_ref();
+	let file = std::fs::File::open(path)
+		.with_context(|| format!("Failed to open the point cloud {}", path.display()))?;
+	let mut reader = BufReader::new(file);
+	let points = if path.extension().is_some_and(|extension| extension == "ply") {
+		read_ply(&mut reader)
+	} else {
+		read_csv(&mut reader)
+	}
+	.with_context(|| format!("Invalid point cloud in {}", path.display()))?;
+
+	Ok(points
+		.into_iter()
+		.map(|point| {
+			let mut sphere = Sphere {
+				position: point.position,
+				radius: point.radius.unwrap_or(radius),
+				color: DEFAULT_COLOR,
+				emission: Vec3::ZERO,
+				metallic: 0.0,
+				texture: None,
+				emissive_texture: None,
+				casts_shadow: true,
+				visible_to_camera: true,
+				max_bounces: None,
+			};
+			if let Some(color) = point.color {
+				sphere.set_srgb_color(color.extend(1.0));
+			}
+			sphere
+		})
+		.collect())
+}
+
+/// Center and radius of a sphere around all `spheres`, `None` if there are none
+pub fn bounds(spheres: &[Sphere]) -> Option<(Vec3, f32)> {
+	let (min, max) = spheres.iter().fold(
+		(Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+		|(min, max), sphere| {
+			(
+				min.min(sphere.position - sphere.radius),
+				max.max(sphere.position + sphere.radius),
+			)
+		},
+	);
+	(!spheres.is_empty()).then(|| ((min + max) / 2.0, (max - min).length() / 2.0))
+}
+
+/// Fails for more points than the renderer can hold, instead of dropping most of them unnoticed
+fn check_count(count: usize) -> anyhow::Result<()> {
+	if count > MAX_SPHERES {
+		anyhow::bail!("The cloud has {count} points, at most {MAX_SPHERES} are supported");
+	}
+	Ok(())
+}
+
+struct Point {
+	position: Vec3,
+	radius: Option<f32>,
+	/// sRGB from 0 to 1
+	color: Option<Vec3>,
+}
+
+/// Columns of the properties a point is made of, other columns are ignored
+struct Columns {
+	position: [usize; 3],
+	radius: Option<usize>,
+	color: Option<[usize; 3]>,
+}
+
+impl Columns {
+	fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> anyhow::Result<Self> {
+		let names: Vec<_> = names.into_iter().collect();
+		let column = |candidates: &[&str]| names.iter().position(|name| candidates.contains(name));
+		let position = [column(&["x"]), column(&["y"]), column(&["z"])];
+		let [Some(x), Some(y), Some(z)] = position else {
+			anyhow::bail!("Points need x, y and z coordinates, found {names:?}");
+		};
+		let color = [
+			column(&["red", "r"]),
+			column(&["green", "g"]),
+			column(&["blue", "b"]),
+		];
+		Ok(Self {
+			position: [x, y, z],
+			radius: column(&["radius"]),
+			color: color[0]
+				.zip(color[1])
+				.zip(color[2])
+				.map(|((r, g), b)| [r, g, b]),
+		})
+	}
+
+	/// Columns of csv files without a header
+	fn from_count(count: usize) -> anyhow::Result<Self> {
+		let (radius, color) = match count {
+			3 => (None, None),
+			4 => (Some(3), None),
+			6 => (None, Some([3, 4, 5])),
+			7 => (Some(3), Some([4, 5, 6])),
+			_ => anyhow::bail!("Expected 3, 4, 6 or 7 columns without a header, found {count}"),
+		};
+		Ok(Self {
+			position: [0, 1, 2],
+			radius,
+			color,
+		})
+	}
+
+	fn point(&self, values: &[f32]) -> Point {
+		Point {
+			position: Vec3::from(self.position.map(|column| values[column])),
+			radius: self.radius.map(|column| values[column]),
+			color: self
+				.color
+				.map(|columns| Vec3::from(columns.map(|column| values[column]))),
+		}
+	}
+}
+
+fn read_csv(reader: &mut impl BufRead) -> anyhow::Result<Vec<Point>> {
+	let mut columns = None;
+	let mut points = Vec::new();
+	for (index, line) in reader.lines().enumerate() {
+		let line = line?;
+		let line = line.trim();
+		if line.is_empty() || line.starts_with('#') {
+			continue;
+		}
+		let fields: Vec<_> = if line.contains(',') {
+			line.split(',').map(str::trim).collect()
+		} else {
+			line.split_whitespace().collect()
+		};
+		let values: Result<Vec<f32>, _> = fields.iter().map(|field| field.parse()).collect();
+		let values = match (values, &columns) {
+			(Ok(values), _) => values,
+			(Err(_), None) => {
+				columns = Some(Columns::from_names(fields)?);
+				continue;
+			}
+			(Err(err), Some(_)) => anyhow::bail!("Line {}: {err}", index + 1),
+		};
+		let columns = match &columns {
+			Some(columns) => columns,
+			None => columns.insert(Columns::from_count(values.len())?),
+		};
+		let mut needed = columns
+			.position
+			.iter()
+			.chain(&columns.radius)
+			.chain(columns.color.iter().flatten());
+		if needed.any(|&column| column >= values.len()) {
+			anyhow::bail!("Line {} has only {} columns", index + 1, values.len());
+		}
+		// Checked while reading, so huge files fail before filling up the memory
+		check_count(points.len() + 1)?;
+		points.push(columns.point(&values));
+	}
+
+	let brightest = points
+		.iter()
+		.filter_map(|point| point.color)
+		.fold(0.0, |brightest: f32, color| {
+			brightest.max(color.max_element())
+		});
+	if brightest > 1.0 {
+		for color in points.iter_mut().filter_map(|point| point.color.as_mut()) {
+			*color /= 255.0;
+		}
+	}
+	Ok(points)
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PlyFormat {
+	Ascii,
+	LittleEndian,
+	BigEndian,
+}
+
+/// Type of a scalar property in a ply file
+#[derive(Copy, Clone)]
+enum PlyType {
+	I8,
+	U8,
+	I16,
+	U16,
+	I32,
+	U32,
+	F32,
+	F64,
+}
+
+impl PlyType {
+	fn parse(name: &str) -> anyhow::Result<Self> {
+		Ok(match name {
+			"char" | "int8" => Self::I8,
+			"uchar" | "uint8" => Self::U8,
+			"short" | "int16" => Self::I16,
+			"ushort" | "uint16" => Self::U16,
+			"int" | "int32" => Self::I32,
+			"uint" | "uint32" => Self::U32,
+			"float" | "float32" => Self::F32,
+			"double" | "float64" => Self::F64,
+			_ => anyhow::bail!("Unknown property type {name}"),
+		})
+	}
+
+	fn size(self) -> usize {
+		match self {
+			Self::I8 | Self::U8 => 1,
+			Self::I16 | Self::U16 => 2,
+			Self::I32 | Self::U32 | Self::F32 => 4,
+			Self::F64 => 8,
+		}
+	}
+
+	fn read(self, bytes: &[u8], format: PlyFormat) -> f32 {
+		macro_rules! from_bytes {
+			($type:ty) => {{
+				let bytes = bytes.try_into().unwrap();
+				if format == PlyFormat::BigEndian {
+					<$type>::from_be_bytes(bytes) as f32
+				} else {
+					<$type>::from_le_bytes(bytes) as f32
+				}
+			}};
+		}
+		match self {
+			Self::I8 => from_bytes!(i8),
+			Self::U8 => from_bytes!(u8),
+			Self::I16 => from_bytes!(i16),
+			Self::U16 => from_bytes!(u16),
+			Self::I32 => from_bytes!(i32),
+			Self::U32 => from_bytes!(u32),
+			Self::F32 => from_bytes!(f32),
+			Self::F64 => from_bytes!(f64),
+		}
+	}
+}
+
+fn read_ply(reader: &mut impl BufRead) -> anyhow::Result<Vec<Point>> {
+	let mut lines = Vec::new();
+	loop {
+		let mut line = String::new();
+		if reader.read_line(&mut line)? == 0 {
+			anyhow::bail!("The header has no end_header line");
+		}
+		let line = line.trim().to_owned();
+		if line == "end_header" {
+			break;
+		}
+		lines.push(line);
+	}
+	if lines.first().map(String::as_str) != Some("ply") {
+		anyhow::bail!("Not a ply file");
+	}
+
+	let mut format = None;
+	// lines of ascii elements before the vertices, which have to be skipped
+	let mut skipped_lines = 0;
+	let mut vertex_count = None;
+	let mut in_vertex = false;
+	let mut properties = Vec::new();
+	for line in &lines[1..] {
+		let words: Vec<_> = line.split_whitespace().collect();
+		match words.as_slice() {
+			["format", "ascii", _] => format = Some(PlyFormat::Ascii),
+			["format", "binary_little_endian", _] => format = Some(PlyFormat::LittleEndian),
+			["format", "binary_big_endian", _] => format = Some(PlyFormat::BigEndian),
+			["format", ..] => anyhow::bail!("Unsupported {line}"),
+			["element", "vertex", count] => {
+				vertex_count = Some(count.parse::<usize>()?);
+				in_vertex = true;
+			}
+			["element", _, count] => {
+				if vertex_count.is_none() {
+					if format != Some(PlyFormat::Ascii) {
+						anyhow::bail!("Vertices have to be the first element of binary files");
+					}
+					skipped_lines += count.parse::<usize>()?;
+				}
+				in_vertex = false;
+			}
+			["property", "list", ..] if in_vertex => {
+				anyhow::bail!("Vertices can't have list properties")
+			}
+			["property", kind, name] if in_vertex => {
+				properties.push((PlyType::parse(kind)?, *name))
+			}
+			_ => {}
+		}
+	}
+	let format = format.context("The header has no format")?;
+	let count = vertex_count.context("There are no vertices")?;
+	// Also keeps a corrupt count from allocating more than the renderer can hold
+	check_count(count)?;
+	let columns = Columns::from_names(properties.iter().map(|(_, name)| *name))?;
+	// 8 bit colors go from 0 to 255, everything else from 0 to 1
+	let color_scale = match columns.color.map(|[r, _, _]| properties[r].0) {
+		Some(PlyType::U8) => 1.0 / 255.0,
+		_ => 1.0,
+	};
+
+	let mut points = Vec::with_capacity(count);
+	let mut values = vec![0.0; properties.len()];
+	if format == PlyFormat::Ascii {
+		let mut lines = reader.lines().skip(skipped_lines);
+		for index in 0..count {
+			let line = lines
+				.next()
+				.context("The file ends before the last vertex")??;
+			let fields: Vec<_> = line.split_whitespace().collect();
+			if fields.len() < values.len() {
+				anyhow::bail!("Vertex {index} has only {} values", fields.len());
+			}
+			for (value, field) in values.iter_mut().zip(fields) {
+				*value = field
+					.parse()
+					.with_context(|| format!("Vertex {index}: invalid value {field}"))?;
+			}
+			points.push(columns.point(&values));
+		}
+	} else {
+		let stride = properties.iter().map(|(kind, _)| kind.size()).sum();
+		let mut vertex = vec![0; stride];
+		for _ in 0..count {
+			reader
+				.read_exact(&mut vertex)
+				.context("The file ends before the last vertex")?;
+			let mut offset = 0;
+			for (value, (kind, _)) in values.iter_mut().zip(&properties) {
+				*value = kind.read(&vertex[offset..offset + kind.size()], format);
+				offset += kind.size();
+			}
+			points.push(columns.point(&values));
+		}
+	}
+	for color in points.iter_mut().filter_map(|point| point.color.as_mut()) {
+		*color *= color_scale;
+	}
+	Ok(points)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn positions(points: &[Point]) -> Vec<Vec3> {
+		points.iter().map(|point| point.position).collect()
+	}
+
+	fn assert_color(point: &Point, expected: Vec3) {
+		let color = point.color.expect("The point has no color");
+		assert!(
+			color.abs_diff_eq(expected, 1e-6),
+			"{color} instead of {expected}"
+		);
+	}
+
+	#[test]
+	fn ascii_ply() {
+		let file = b"ply\nformat ascii 1.0\ncomment skipped\nelement face 1\nproperty list uchar int vertex_indices\n\
+			element vertex 2\nproperty float x\nproperty float y\nproperty float z\nproperty float radius\n\
+			property uchar red\nproperty uchar green\nproperty uchar blue\nend_header\n\
+			3 0 1 2\n1 2 3 0.5 255 0 51\n-1 0 0 2 0 255 0\n";
+		let points = read_ply(&mut &file[..]).unwrap();
+		assert_eq!(
+			positions(&points),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(-1.0, 0.0, 0.0)]
+		);
+		assert_eq!(points[0].radius, Some(0.5));
+		assert_color(&points[0], Vec3::new(1.0, 0.0, 0.2));
+		assert_color(&points[1], Vec3::Y);
+	}
+
+	#[test]
+	fn little_endian_ply() {
+		let mut file = b"ply\nformat binary_little_endian 1.0\nelement vertex 2\n\
+			property float x\nproperty float y\nproperty double z\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+			end_header\n"
+			.to_vec();
+		for (x, y, z, color) in [
+			(1.0f32, 2.0f32, 3.0f64, [255, 0, 0]),
+			(-4.0, 5.0, -6.0, [0, 0, 255]),
+		] {
+			file.extend(x.to_le_bytes());
+			file.extend(y.to_le_bytes());
+			file.extend(z.to_le_bytes());
+			file.extend(color);
+		}
+		let points = read_ply(&mut file.as_slice()).unwrap();
+		assert_eq!(
+			positions(&points),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(-4.0, 5.0, -6.0)]
+		);
+		assert_eq!(points[0].radius, None);
+		assert_color(&points[0], Vec3::X);
+		assert_color(&points[1], Vec3::Z);
+	}
+
+	#[test]
+	fn ply_with_too_many_vertices() {
+		let file =
+			b"ply\nformat binary_little_endian 1.0\nelement vertex 99999999999\nproperty float x\n\
+			property float y\nproperty float z\nend_header\n";
+		assert!(read_ply(&mut &file[..]).is_err());
+	}
+
+	#[test]
+	fn csv_with_header() {
+		let file = b"# comment\nz, x, y, radius\n3, 1, 2, 0.5\n\n6, 4, 5, 1\n";
+		let points = read_csv(&mut &file[..]).unwrap();
+		assert_eq!(
+			positions(&points),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)]
+		);
+		assert_eq!(points[1].radius, Some(1.0));
+		assert_eq!(points[0].color, None);
+	}
+
+	#[test]
+	fn csv_without_header() {
+		let file = b"1 2 3 0.5 0.2 0.4\n4 5 6 1 0 0\n";
+		let points = read_csv(&mut &file[..]).unwrap();
+		assert_eq!(
+			positions(&points),
+			[Vec3::new(1.0, 2.0, 3.0), Vec3::new(4.0, 5.0, 6.0)]
+		);
+		assert_eq!(points[0].radius, None);
+		assert_color(&points[0], Vec3::new(0.5, 0.2, 0.4));
+		assert!(read_csv(&mut &b"1 2 3 4 5\n"[..]).is_err());
+	}
+
+	#[test]
+	fn csv_colors_up_to_255() {
+		let file = b"x,y,z,r,g,b\n0,0,0,255,51,0\n1,1,1,0,0,0.5\n";
+		let points = read_csv(&mut &file[..]).unwrap();
+		assert_color(&points[0], Vec3::new(1.0, 0.2, 0.0));
+		assert_color(&points[1], Vec3::new(0.0, 0.0, 0.5 / 255.0));
+	}
+}
@@ -0,0 +1,136 @@
+// Fullscreen pass that turns the hdr radiance written by shader.wgsl into the final image
+
+struct VertexInput {
+  @location(0) position: vec3<f32>,
+  @location(1) uv: vec2<f32>,
+};
+
+struct VertexOutput {
+  @builtin(position) position: vec4<f32>,
+  @location(1) uv: vec2<f32>
+};
+
+@vertex
+fn vs_main(
+    in: VertexInput
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = vec4(in.position, 1.0);
+    out.uv = in.uv;
+    return out;
+}
+
+struct Post {
+  // 0 if bloom is disabled
+  bloom_intensity: f32,
+  // 1 if the target doesn't convert to sRGB by itself
+  encode_srgb: u32,
+  // distance in hdr texels to the neighbors compared by outline, 0 if outlines are disabled
+  outline_thickness: f32,
+  // brightness levels of cel_shade, 0 if it is disabled
+  toon_bands: u32,
+  outline_color: vec3<f32>,
+}
+
+@group(0)
+@binding(0)
+var hdr: texture_2d<f32>;
+@group(0)
+@binding(1)
+var bloom: texture_2d<f32>;
+// linear, used for both textures
+@group(0)
+@binding(2)
+var linear_sampler: sampler;
+@group(0)
+@binding(3)
+var<uniform> post: Post;
+// distance to the nearest surface, at the size of hdr
+@group(0)
+@binding(4)
+var depth: texture_2d<f32>;
+// world space normal of the nearest surface, zero for the sky
+@group(0)
+@binding(5)
+var normals: texture_2d<f32>;
+
+fn composite(quad_uv: vec2<f32>) -> vec4<f32> {
+    // The hdr texture is smaller than the target if the render scale is below 1,
+    // at the same size every pixel center lies exactly on a texel center
+    let uv = vec2(quad_uv.x, -quad_uv.y) * 0.5 + 0.5;
+    var color = textureSample(hdr, linear_sampler, uv);
+    if post.bloom_intensity > 0.0 {
+        let glow = textureSample(bloom, linear_sampler, uv).rgb;
+        color = vec4(color.rgb + glow * post.bloom_intensity, color.a);
+    }
+    if post.toon_bands > 0u {
+        color = vec4(cel_shade(color.rgb), color.a);
+    }
+    if post.outline_thickness > 0.0 {
+        let coverage = outline(vec2<i32>(uv * vec2<f32>(textureDimensions(depth))));
+        color = vec4(mix(color.rgb, post.outline_color, coverage), max(color.a, coverage));
+    }
+    return color;
+}
+
+// Rounds the brightness up to one of post.toon_bands levels, keeping the hue
+fn cel_shade(color: vec3<f32>) -> vec3<f32> {
+    let luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+    if luminance <= 0.0 {
+        return color;
+    }
+    let bands = f32(post.toon_bands);
+    return color * (ceil(luminance * bands) / bands / luminance);
+}
+
+// 1 if the texel lies on a silhouette or crease, found by comparing it to the texels
+// post.outline_thickness away on either side
+fn outline(texel: vec2<i32>) -> f32 {
+    let offset = max(i32(round(post.outline_thickness)), 1);
+    let last = vec2<i32>(textureDimensions(depth)) - 1;
+    // inverse depth changes linearly across flat surfaces, so only curves and steps stand out
+    let center = 1.0 / textureLoad(depth, texel, 0).r;
+    let normal = textureLoad(normals, texel, 0).xyz;
+    var edge = false;
+    for (var axis = 0; axis < 2; axis += 1) {
+        var shift = vec2(0);
+        shift[axis] = offset;
+        let before = clamp(texel - shift, vec2(0), last);
+        let after = clamp(texel + shift, vec2(0), last);
+        let curvature = 1.0 / textureLoad(depth, before, 0).r + 1.0 / textureLoad(depth, after, 0).r - 2.0 * center;
+        edge = edge || abs(curvature) > 0.1 * center;
+        // the sky has no normal, so silhouettes against it are found here as well
+        let crease = min(
+            dot(normal, textureLoad(normals, before, 0).xyz),
+            dot(normal, textureLoad(normals, after, 0).xyz)
+        );
+        edge = edge || (any(normal != vec3(0.0)) && crease < 0.7);
+    }
+    return select(0.0, 1.0, edge);
+}
+
+// Does what an sRGB target does when it is written to, so every target shows the same image
+fn encode(color: vec4<f32>) -> vec4<f32> {
+    if post.encode_srgb == 0u {
+        return color;
+    }
+    let linear = clamp(color.rgb, vec3(0.0), vec3(1.0));
+    let srgb = select(
+        1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055,
+        linear * 12.92,
+        linear <= vec3(0.0031308)
+    );
+    return vec4(srgb, color.a);
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return encode(composite(in.uv));
+}
+
+// Same as fs_main, but with the color multiplied by alpha for compositing
+@fragment
+fn fs_premultiplied(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = composite(in.uv);
+    return encode(vec4(color.rgb * color.a, color.a));
+}
@@ -0,0 +1,175 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+
+/// Most scopes a single frame can record
+const MAX_SCOPES: u32 = 8;
+const TIMESTAMP_SIZE: wgpu::BufferAddress = std::mem::size_of::<u64>() as wgpu::BufferAddress;
+
+/// Timestamp query set and the buffers its results are read back through
+struct Queries {
+	set: wgpu::QuerySet,
+	resolve_buffer: wgpu::Buffer,
+	readback_buffer: wgpu::Buffer,
+	/// Nanoseconds per timestamp tick
+	period: f32,
+}
+
+/// Timestamps of a submitted frame on their way back from the gpu
+struct Readback {
+	scopes: Vec<&'static str>,
+	/// Receives the result of mapping the readback buffer
+	mapped: Receiver<Result<(), wgpu::BufferAsyncError>>,
+}
+
+/// Measures how long the gpu spends on each render pass with timestamp queries.
+///
+/// Does nothing if `timestamp_queries` was false when creating it.
+pub(crate) struct Profiler {
+	queries: Option<Queries>,
+	/// Whether the current frame writes timestamps, which it doesn't while the previous one is read back
+	recording: bool,
+	/// Scopes ended since [`Profiler::begin`], scope `i` lasts from timestamp `i` to `i + 1`
+	scopes: Vec<&'static str>,
+	reading_back: Option<Readback>,
+	/// Milliseconds spent on each scope of the last frame that was read back
+	timings: BTreeMap<&'static str, f32>,
+}
+
+impl Profiler {
+	/// `timestamp_queries` has to be a feature of `device`
+	pub(crate) fn new(device: &wgpu::Device, queue: &wgpu::Queue, timestamp_queries: bool) -> Self {
+		let queries = timestamp_queries.then(|| {
+			let size = (MAX_SCOPES + 1) as wgpu::BufferAddress * TIMESTAMP_SIZE;
+			Queries {
+				set: device.create_query_set(&wgpu::QuerySetDescriptor {
+					label: Some("Profiler Query Set"),
+					ty: wgpu::QueryType::Timestamp,
+					count: MAX_SCOPES + 1,
+				}),
+				resolve_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+					label: Some("Profiler Resolve Buffer"),
+					size,
+					usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+					mapped_at_creation: false,
+				}),
+				readback_buffer: device.create_buffer(&wgpu::BufferDescriptor {
+					label: Some("Profiler Readback Buffer"),
+					size,
+					usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+					mapped_at_creation: false,
+				}),
+				period: queue.get_timestamp_period(),
+			}
+		});
+
+		Self {
+			queries,
+			recording: false,
+			scopes: Vec::new(),
+			reading_back: None,
+			timings: BTreeMap::new(),
+		}
+	}
+
+	/// Starts the first scope of a frame
+	pub(crate) fn begin(&mut self, encoder: &mut wgpu::CommandEncoder) {
+		self.scopes.clear();
+		self.recording = self.reading_back.is_none();
+		if let (true, Some(queries)) = (self.recording, &self.queries) {
+			encoder.write_timestamp(&queries.set, 0);
+		}
+	}
+
+	/// Ends the current scope as `name` and starts the next one
+	pub(crate) fn end_scope(&mut self, encoder: &mut wgpu::CommandEncoder, name: &'static str) {
+		let Some(queries) = &self.queries else {
+			return;
+		};
+		if !self.recording || self.scopes.len() >= MAX_SCOPES as usize {
+			return;
+		}
+		self.scopes.push(name);
+		encoder.write_timestamp(&queries.set, self.scopes.len() as u32);
+	}
+
+	/// Copies the timestamps of the frame into the readback buffer,
+	/// [`Profiler::map`] has to be called after `encoder` was submitted
+	pub(crate) fn finish(&self, encoder: &mut wgpu::CommandEncoder) {
+		let Some(queries) = &self.queries else {
+			return;
+		};
+		if !self.recording || self.scopes.is_empty() {
+			return;
+		}
+		let count = self.scopes.len() as u32 + 1;
+		encoder.resolve_query_set(&queries.set, 0..count, &queries.resolve_buffer, 0);
+		encoder.copy_buffer_to_buffer(
+			&queries.resolve_buffer,
+			0,
+			&queries.readback_buffer,
+			0,
+			count as wgpu::BufferAddress * TIMESTAMP_SIZE,
+		);
+	}
+
+	/// Requests mapping the timestamps, which completes during a later `device.poll`
+	pub(crate) fn map(&mut self) {
+		let Some(queries) = &self.queries else {
+			return;
+		};
+		if !self.recording || self.scopes.is_empty() {
+			return;
+		}
+		self.recording = false;
+		let (sender, receiver) = mpsc::channel();
+		queries
+			.readback_buffer
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, move |result| {
+				let _ = sender.send(result);
+			});
+		self.reading_back = Some(Readback {
+			scopes: std::mem::take(&mut self.scopes),
+			mapped: receiver,
+		});
+	}
+
+	/// Whether timestamps are waiting for the device to be polled
+	pub(crate) fn is_reading_back(&self) -> bool {
+		self.reading_back.is_some()
+	}
+
+	/// Updates the timings if the timestamps finished mapping
+	pub(crate) fn try_read(&mut self) {
+		let (Some(queries), Some(Readback { scopes, mapped })) =
+			(&self.queries, &self.reading_back)
+		else {
+			return;
+		};
+		match mapped.try_recv() {
+			Err(TryRecvError::Empty) => return,
+			Err(TryRecvError::Disconnected) => eprintln!("Profiler buffer was never mapped"),
+			Ok(Err(err)) => eprintln!("Failed to map profiler buffer: {err}"),
+			Ok(Ok(())) => {
+				{
+					let data = queries.readback_buffer.slice(..).get_mapped_range();
+					let timestamps: &[u64] = bytemuck::cast_slice(&data);
+					self.timings = scopes
+						.iter()
+						.zip(timestamps.windows(2))
+						.map(|(&name, window)| {
+							let ticks = window[1].saturating_sub(window[0]);
+							(name, ticks as f32 * queries.period / 1_000_000.0)
+						})
+						.collect();
+				}
+				queries.readback_buffer.unmap();
+			}
+		}
+		self.reading_back = None;
+	}
+
+	pub(crate) fn timings(&self) -> &BTreeMap<&'static str, f32> {
+		&self.timings
+	}
+}
@@ -1,22 +1,739 @@
-use glam::{Vec2, Vec3, Vec4};
+use anyhow::Context;
+use bytemuck::Zeroable;
+use glam::{Quat, Vec2, Vec3, Vec4, Vec4Swizzles};
+use half::f16;
+use serde::{Deserialize, Serialize};
 use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
 use wgpu::util::DeviceExt;
 
+use crate::bloom::{Bloom, BloomTarget};
+use crate::camera::Camera;
+use crate::capabilities::{Capabilities, REQUIRED_BIND_GROUPS, REQUIRED_STORAGE_BUFFERS};
+use crate::capture::PendingCapture;
+use crate::color;
+use crate::config::{AppConfig, DevicePolling};
+use crate::overlay::Overlay;
+use crate::profiler::Profiler;
+use crate::scene::Scene;
+use crate::settings::RenderSettings;
+use crate::textures::{MaterialTextures, MAX_TEXTURES};
+
 #[repr(C)]
-#[repr(align(8))]
+#[repr(align(16))]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
+	position: Vec3,
 	width: u32,
+	forward: Vec3,
 	height: u32,
+	up: Vec3,
+	/// Vertical field of view in radians
+	fov: f32,
+	near: f32,
+	far: f32,
+	_padding: [u32; 2],
+	/// Center (xy) and size (zw) of the traced image within the whole image in uv coordinates,
+	/// [`FULL_TILE`] unless rendering with [`Renderer::render_tile`]
+	tile: Vec4,
+}
+
+/// [`CameraUniform::tile`] covering the whole image
+const FULL_TILE: Vec4 = Vec4::new(0.0, 0.0, 1.0, 1.0);
+
+/// Whether debug builds catch validation errors while rendering a frame.
+/// Waiting for the error scope would block the browser, so on the web they go to the error handler of the device
+const CATCH_VALIDATION_ERRORS: bool = cfg!(all(debug_assertions, not(target_arch = "wasm32")));
+
+/// Camera movement relative to the distance to its origin that discards the history, see [`CameraUniform::is_jump`]
+const HISTORY_MAX_MOVE: f32 = 0.5;
+
+// Layout of `Camera` in shader.wgsl, a vec3 takes 12 bytes but is aligned to 16
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(CameraUniform, width) == 12);
+	assert!(offset_of!(CameraUniform, forward) == 16);
+	assert!(offset_of!(CameraUniform, height) == 28);
+	assert!(offset_of!(CameraUniform, up) == 32);
+	assert!(offset_of!(CameraUniform, fov) == 44);
+	assert!(offset_of!(CameraUniform, near) == 48);
+	assert!(offset_of!(CameraUniform, far) == 52);
+	assert!(offset_of!(CameraUniform, tile) == 64);
+	assert!(size_of::<CameraUniform>() == 80);
+};
+
+impl CameraUniform {
+	fn new(camera: &Camera, width: u32, height: u32) -> Self {
+		Self {
+			position: camera.position(),
+			width,
+			forward: camera.forward(),
+			height,
+			up: camera.up(),
+			fov: camera.fov.to_radians(),
+			near: camera.near,
+			far: camera.far,
+			_padding: [0; 2],
+			tile: FULL_TILE,
+		}
+	}
+
+	/// Direction of the ray through `uv`, which goes from -1 to 1 with y pointing up, like `camera_ray` in the shader
+	fn ray_direction(&self, uv: Vec2) -> Vec3 {
+		let uv = self.tile.xy() + uv * self.tile.zw();
+		let aspect_ratio = self.width as f32 / self.height as f32 * self.tile.w / self.tile.z;
+		let height = (self.fov / 2.0).tan();
+		let width = height * aspect_ratio;
+		let right = self.up.cross(self.forward);
+		(self.forward + right * uv.x * width + self.up * uv.y * height).normalize()
+	}
+
+	/// Whether `next` shows so little of what this camera saw that its image can't be reprojected,
+	/// because it turned by more than half the field of view or moved by more than [`HISTORY_MAX_MOVE`]
+	/// times `distance`, the distance to what the camera looks at
+	fn is_jump(&self, next: &Self, distance: f32) -> bool {
+		self.forward.angle_between(next.forward) > self.fov / 2.0
+			|| self.position.distance(next.position) > distance * HISTORY_MAX_MOVE
+	}
+
+	/// Inverse of [`CameraUniform::ray_direction`], `None` for points behind the near plane
+	fn project(&self, point: Vec3) -> Option<Vec2> {
+		let offset = point - self.position;
+		let depth = offset.dot(self.forward);
+		if depth < self.near {
+			return None;
+		}
+		let aspect_ratio = self.width as f32 / self.height as f32 * self.tile.w / self.tile.z;
+		let height = (self.fov / 2.0).tan();
+		let width = height * aspect_ratio;
+		let right = self.up.cross(self.forward);
+		let uv = Vec2::new(
+			offset.dot(right) / (depth * width),
+			offset.dot(self.up) / (depth * height),
+		);
+		Some((uv - self.tile.xy()) / self.tile.zw())
+	}
 }
 
 #[repr(C)]
 #[repr(align(16))]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct SettingsUniform {
+	sky_zenith: Vec3,
+	samples_per_pixel: u32,
+	sky_horizon: Vec3,
+	max_bounces: u32,
+	/// Number of frames accumulated before this one, `0` discards the accumulated samples
+	frame: u32,
+	analytic_antialiasing: u32,
+	env_intensity: f32,
+	/// Number of textures added to the renderer, higher indices are ignored
+	texture_count: u32,
+	/// Number of spheres in the objects buffer, which holds a placeholder if there are none
+	sphere_count: u32,
+	shadow_samples: u32,
+	gi_samples: u32,
+	/// [`DebugView`](crate::settings::DebugView) as an integer, see the constants in the shader
+	debug_view: u32,
+	/// Number of boxes in the obb buffer, which holds a placeholder if there are none
+	obb_count: u32,
+	/// Number of lights in the light buffer, which holds a placeholder if there are none
+	light_count: u32,
+	max_intersection_tests: u32,
+	/// `1` if the history texture holds the previous frame, see [`AppConfig::temporal_history`]
+	history_valid: u32,
+	antialiasing_width: f32,
+	seed: u32,
+	_padding: [u32; 2],
+}
+
+// Layout of `Settings` in shader.wgsl, uniform structs are padded to a multiple of 16 bytes
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(SettingsUniform, samples_per_pixel) == 12);
+	assert!(offset_of!(SettingsUniform, sky_horizon) == 16);
+	assert!(offset_of!(SettingsUniform, max_bounces) == 28);
+	assert!(offset_of!(SettingsUniform, frame) == 32);
+	assert!(offset_of!(SettingsUniform, texture_count) == 44);
+	assert!(offset_of!(SettingsUniform, sphere_count) == 48);
+	assert!(offset_of!(SettingsUniform, debug_view) == 60);
+	assert!(offset_of!(SettingsUniform, obb_count) == 64);
+	assert!(offset_of!(SettingsUniform, light_count) == 68);
+	assert!(offset_of!(SettingsUniform, max_intersection_tests) == 72);
+	assert!(offset_of!(SettingsUniform, history_valid) == 76);
+	assert!(offset_of!(SettingsUniform, antialiasing_width) == 80);
+	assert!(offset_of!(SettingsUniform, seed) == 84);
+	assert!(size_of::<SettingsUniform>() == 96);
+};
+
+impl SettingsUniform {
+	fn new(
+		settings: &RenderSettings,
+		frame: u32,
+		texture_count: u32,
+		sphere_count: u32,
+		obb_count: u32,
+		light_count: u32,
+		history_valid: bool,
+	) -> Self {
+		Self {
+			sky_zenith: settings.sky.zenith,
+			samples_per_pixel: settings.samples_per_pixel.max(1),
+			sky_horizon: settings.sky.horizon,
+			max_bounces: settings.max_bounces,
+			frame,
+			analytic_antialiasing: settings.analytic_antialiasing as u32,
+			env_intensity: settings.env_intensity,
+			texture_count,
+			sphere_count,
+			shadow_samples: settings.shadow_samples.max(1),
+			gi_samples: settings.gi_samples.max(1),
+			debug_view: settings.debug_view as u32,
+			obb_count,
+			light_count,
+			max_intersection_tests: settings.max_intersection_tests,
+			history_valid: history_valid.into(),
+			antialiasing_width: settings.antialiasing_width.max(0.0),
+			seed: settings.seed,
+			_padding: [0; 2],
+		}
+	}
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Sphere {
 	pub position: Vec3,
 	pub radius: f32,
+	/// Linear color, use [`Sphere::set_srgb_color`] for colors taken from a color picker
 	pub color: Vec4,
+	/// Linear radiance emitted by the surface, not limited to `1`, a bright bulb can use `10` or more.
+	/// The post pass tonemaps the result
+	#[serde(default)]
+	pub emission: Vec3,
+	/// Probability of a ray being reflected like by a mirror instead of scattered diffusely
+	#[serde(default)]
+	pub metallic: f32,
+	/// Index returned by [`Renderer::add_texture`] of an image that is wrapped around the sphere
+	/// and multiplied with `color`
+	#[serde(default)]
+	pub texture: Option<u32>,
+	/// Index returned by [`Renderer::add_texture`] of an image that is wrapped around the sphere like `texture`
+	/// and multiplied with `emission`, for screens or signs. Set `emission` to its brightness, the sphere stays dark
+	/// where it is zero. Emitters are chosen for next event estimation regardless of their texture,
+	/// which is looked up at the sampled point, so dark parts of a texture only waste some shadow rays
+	#[serde(default)]
+	pub emissive_texture: Option<u32>,
+	/// Whether the sphere blocks shadow rays, can be turned off for lights or helper geometry
+	#[serde(default = "default_casts_shadow")]
+	pub casts_shadow: bool,
+	/// Whether rays from the camera hit the sphere, turn it off for lights that should
+	/// only be seen through the light they cast and their reflections
+	#[serde(default = "default_visible_to_camera")]
+	pub visible_to_camera: bool,
+	/// Ends paths that hit the sphere after this many bounces, e.g. to keep diffuse surfaces cheap.
+	/// Can only lower [`RenderSettings::max_bounces`], `None` or values above it leave the global limit,
+	/// values above [`MAX_MATERIAL_BOUNCES`] are clamped
+	#[serde(default)]
+	pub max_bounces: Option<u32>,
+}
+
+fn default_casts_shadow() -> bool {
+	true
+}
+
+fn default_visible_to_camera() -> bool {
+	true
+}
+
+/// Most spheres the renderer uploads, the rest are dropped by [`Renderer::update_spheres`].
+/// The shader gets the same value as `max_spheres` and never loops further.
+/// Devices with small storage buffer bindings hold fewer, see [`Capabilities`].
+/// Every ray is tested against every sphere, scenes this large need a [`RenderSettings::max_intersection_tests`] budget
+pub const MAX_SPHERES: usize = 1 << 20;
+
+/// Radius a sphere can exceed another one containing it by, relative to the container,
+/// so coincident spheres are found despite rounding
+const CONTAINMENT_TOLERANCE: f32 = 1e-5;
+
+/// Most pairs listed by the warning about contained spheres, the count includes all of them
+const MAX_LISTED_PAIRS: usize = 8;
+
+/// Bit of [`GpuSphere::flags`] set for spheres that block shadow rays
+const CASTS_SHADOW_FLAG: u32 = 1;
+/// Bit of [`GpuSphere::flags`] set for spheres that are hit by camera rays
+const VISIBLE_TO_CAMERA_FLAG: u32 = 2;
+/// The 8 bits of [`GpuSphere::flags`] from here on store the material bounce limit plus one, zero means none
+const BOUNCES_SHIFT: u32 = 8;
+/// Highest bounce limit of a single sphere or box that fits into its flags
+pub const MAX_MATERIAL_BOUNCES: u32 = 254;
+/// The 8 bits of [`GpuSphere::flags`] from here on store the texture index plus one, zero means untextured
+const TEXTURE_SHIFT: u32 = 16;
+/// The bits of [`GpuSphere::flags`] from here on store the emissive texture index plus one, zero means none
+const EMISSIVE_TEXTURE_SHIFT: u32 = 24;
+
+/// Bounce limit of a material as stored in its flags
+fn bounces_flags(max_bounces: Option<u32>) -> u32 {
+	max_bounces.map_or(0, |bounces| {
+		(bounces.min(MAX_MATERIAL_BOUNCES) + 1) << BOUNCES_SHIFT
+	})
+}
+
+impl Sphere {
+	/// Sets `color` from sRGB values, converting them to linear
+	pub fn set_srgb_color(&mut self, srgb: Vec4) -> &mut Self {
+		self.color = color::srgb_to_linear(srgb);
+		self
+	}
+
+	/// `color` converted to sRGB
+	pub fn srgb_color(&self) -> Vec4 {
+		color::linear_to_srgb(self.color)
+	}
+
+	fn flags(&self) -> u32 {
+		// Indices that can't be stored in 8 bits leave the sphere untextured, like the ones past the added textures
+		let texture_flags = |texture: Option<u32>, shift: u32| {
+			texture
+				.filter(|&index| index < MAX_TEXTURES)
+				.map_or(0, |index| (index + 1) << shift)
+		};
+		let mut flags = texture_flags(self.texture, TEXTURE_SHIFT)
+			| texture_flags(self.emissive_texture, EMISSIVE_TEXTURE_SHIFT)
+			| bounces_flags(self.max_bounces);
+		if self.casts_shadow {
+			flags |= CASTS_SHADOW_FLAG;
+		}
+		if self.visible_to_camera {
+			flags |= VISIBLE_TO_CAMERA_FLAG;
+		}
+		flags
+	}
+
+	/// Distance along the ray to the nearest hit between `t_min` and `t_max`, like `hit_sphere` in the shader
+	fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+		let a = origin - self.position;
+		let e = direction.dot(direction);
+		let h = a.dot(direction);
+		let g = a.dot(a) - self.radius * self.radius;
+		let closest = a - (h / e) * direction;
+		let d = e * (self.radius * self.radius - closest.dot(closest));
+		if d < 0.0 {
+			return None;
+		}
+		let q = -(h + h.signum() * d.sqrt());
+		let (t0, t1) = (q / e, g / q);
+		[t0.min(t1), t0.max(t1)]
+			.into_iter()
+			.find(|t| (t_min..=t_max).contains(t))
+	}
+
+	/// `false` if any field is NaN or infinite
+	pub fn is_finite(&self) -> bool {
+		self.position.is_finite()
+			&& self.radius.is_finite()
+			&& self.color.is_finite()
+			&& self.emission.is_finite()
+			&& self.metallic.is_finite()
+	}
+}
+
+/// Drops spheres with NaN or infinite fields, which would otherwise silently corrupt the image.
+/// Only checked in debug builds to keep uploads cheap in release builds.
+fn finite_spheres(spheres: &[Sphere]) -> Cow<'_, [Sphere]> {
+	if !cfg!(debug_assertions) || spheres.iter().all(Sphere::is_finite) {
+		return Cow::Borrowed(spheres);
+	}
+	spheres
+		.iter()
+		.enumerate()
+		.filter_map(|(index, sphere)| {
+			if !sphere.is_finite() {
+				log::warn!("Skipping sphere {index} with non-finite values: {sphere:?}");
+				return None;
+			}
+			Some(*sphere)
+		})
+		.collect()
+}
+
+/// [`MAX_SPHERES`], lowered to the spheres that fit into a storage buffer binding of the device
+fn max_spheres(capabilities: &Capabilities, packed: bool) -> usize {
+	let size = if packed {
+		std::mem::size_of::<PackedSphere>()
+	} else {
+		std::mem::size_of::<GpuSphere>()
+	};
+	MAX_SPHERES.min(capabilities.max_storage_buffer_binding_size as usize / size)
+}
+
+/// Finite spheres up to `max_spheres`, warning about the dropped ones
+fn checked_spheres(spheres: &[Sphere], max_spheres: usize) -> Cow<'_, [Sphere]> {
+	let mut spheres = finite_spheres(spheres);
+	if spheres.len() > max_spheres {
+		log::warn!(
+			"Dropping {} spheres, the renderer holds at most {max_spheres}",
+			spheres.len() - max_spheres
+		);
+		spheres.to_mut().truncate(max_spheres);
+	}
+	spheres
+}
+
+/// Pairs of indices of spheres that lie completely inside another one and the sphere containing them,
+/// including coincident spheres. Only containers that are visible to the camera and cast shadows count,
+/// everything inside them can never be hit
+fn contained_spheres(spheres: &[Sphere]) -> Vec<(usize, usize)> {
+	// Sweep along x, a sphere can only contain the ones whose extent along x overlaps with its own
+	let mut order: Vec<usize> = (0..spheres.len()).collect();
+	order.sort_unstable_by(|&a, &b| {
+		let min_x = |index: usize| spheres[index].position.x - spheres[index].radius;
+		min_x(a).total_cmp(&min_x(b))
+	});
+	let contains = |outer: &Sphere, inner: &Sphere| {
+		outer.visible_to_camera
+			&& outer.casts_shadow
+			&& outer.position.distance(inner.position) + inner.radius
+				<= outer.radius * (1.0 + CONTAINMENT_TOLERANCE)
+	};
+	let mut pairs = Vec::new();
+	for (start, &a) in order.iter().enumerate() {
+		let max_x = spheres[a].position.x + spheres[a].radius;
+		for &b in &order[start + 1..] {
+			if spheres[b].position.x - spheres[b].radius > max_x {
+				break;
+			}
+			if contains(&spheres[a], &spheres[b]) {
+				pairs.push((b, a));
+			} else if contains(&spheres[b], &spheres[a]) {
+				pairs.push((a, b));
+			}
+		}
+	}
+	pairs.sort_unstable();
+	pairs
+}
+
+/// Warns about the [`contained_spheres`], see [`AppConfig::validate_spheres`]
+fn warn_contained_spheres(spheres: &[Sphere]) {
+	let pairs = contained_spheres(spheres);
+	if pairs.is_empty() {
+		return;
+	}
+	let listed: Vec<_> = pairs
+		.iter()
+		.take(MAX_LISTED_PAIRS)
+		.map(|(inner, outer)| format!("{inner} in {outer}"))
+		.collect();
+	let more = if pairs.len() > MAX_LISTED_PAIRS {
+		", ..."
+	} else {
+		""
+	};
+	log::warn!(
+		"Found {} pairs of spheres where one is inside or coincident with the other and can never be hit: {}{more}",
+		pairs.len(),
+		listed.join(", ")
+	);
+}
+
+/// Lights up to `max_lights`, warning about the dropped ones
+fn checked_lights(lights: &[Light], max_lights: usize) -> Vec<Light> {
+	if lights.len() > max_lights {
+		log::warn!(
+			"Dropping {} lights, the renderer holds at most {max_lights}",
+			lights.len() - max_lights
+		);
+	}
+	lights[..lights.len().min(max_lights)].to_vec()
+}
+
+/// Box that is rotated around its center, intersected in its own space by the shader
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct Obb {
+	pub center: Vec3,
+	/// Half of the size along each axis of the box, before rotating it
+	pub half_extents: Vec3,
+	#[serde(default)]
+	pub rotation: Quat,
+	/// Linear color
+	pub color: Vec4,
+	/// Light emitted by the surface, only found by paths that hit the box
+	/// because boxes aren't sampled for direct light
+	#[serde(default)]
+	pub emission: Vec3,
+	/// Probability of a ray being reflected like by a mirror instead of scattered diffusely
+	#[serde(default)]
+	pub metallic: f32,
+	/// Whether the box blocks shadow rays
+	#[serde(default = "default_casts_shadow")]
+	pub casts_shadow: bool,
+	/// Whether rays from the camera hit the box
+	#[serde(default = "default_visible_to_camera")]
+	pub visible_to_camera: bool,
+	/// See [`Sphere::max_bounces`]
+	#[serde(default)]
+	pub max_bounces: Option<u32>,
+}
+
+impl Obb {
+	fn flags(&self) -> u32 {
+		let mut flags = bounces_flags(self.max_bounces);
+		if self.casts_shadow {
+			flags |= CASTS_SHADOW_FLAG;
+		}
+		if self.visible_to_camera {
+			flags |= VISIBLE_TO_CAMERA_FLAG;
+		}
+		flags
+	}
+
+	/// Distance along the ray to the nearest hit between `t_min` and `t_max`, like `hit_obb` in the shader
+	fn intersect(&self, origin: Vec3, direction: Vec3, t_min: f32, t_max: f32) -> Option<f32> {
+		let inverse = self.rotation.inverse();
+		let origin = inverse * (origin - self.center);
+		let direction = inverse * direction;
+		let t0 = (-self.half_extents - origin) / direction;
+		let t1 = (self.half_extents - origin) / direction;
+		let near = t0.min(t1).max_element();
+		let far = t0.max(t1).min_element();
+		if near > far {
+			return None;
+		}
+		[near, far]
+			.into_iter()
+			.find(|t| (t_min..=t_max).contains(t))
+	}
+}
+
+/// Layout of an [`Obb`] in the obb buffer
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuObb {
+	center: Vec3,
+	metallic: f32,
+	half_extents: Vec3,
+	flags: u32,
+	/// Quaternion from box to world space
+	rotation: Vec4,
+	color: Vec4,
+	emission: Vec3,
+	_padding: u32,
+}
+
+// Layout of `Obb` in shader.wgsl, elements of a storage array are aligned to their largest member
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(GpuObb, metallic) == 12);
+	assert!(offset_of!(GpuObb, half_extents) == 16);
+	assert!(offset_of!(GpuObb, flags) == 28);
+	assert!(offset_of!(GpuObb, rotation) == 32);
+	assert!(offset_of!(GpuObb, color) == 48);
+	assert!(offset_of!(GpuObb, emission) == 64);
+	assert!(size_of::<GpuObb>() == 80);
+};
+
+impl From<&Obb> for GpuObb {
+	fn from(obb: &Obb) -> Self {
+		Self {
+			center: obb.center,
+			metallic: obb.metallic,
+			half_extents: obb.half_extents,
+			flags: obb.flags(),
+			rotation: Vec4::from(obb.rotation.normalize()),
+			color: obb.color,
+			emission: obb.emission,
+			_padding: 0,
+		}
+	}
+}
+
+/// Layout of a [`Sphere`] in the objects buffer
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuSphere {
+	position: Vec3,
+	radius: f32,
+	color: Vec4,
+	emission: Vec3,
+	metallic: f32,
+	flags: u32,
+	_padding: [u32; 3],
+}
+
+// Layout of `Sphere` in shader.wgsl and spheres.wgsl
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(GpuSphere, radius) == 12);
+	assert!(offset_of!(GpuSphere, color) == 16);
+	assert!(offset_of!(GpuSphere, emission) == 32);
+	assert!(offset_of!(GpuSphere, metallic) == 44);
+	assert!(offset_of!(GpuSphere, flags) == 48);
+	assert!(size_of::<GpuSphere>() == 64);
+};
+
+impl From<&Sphere> for GpuSphere {
+	fn from(sphere: &Sphere) -> Self {
+		Self {
+			position: sphere.position,
+			radius: sphere.radius,
+			color: sphere.color,
+			emission: sphere.emission,
+			metallic: sphere.metallic,
+			flags: sphere.flags(),
+			_padding: [0; 3],
+		}
+	}
+}
+
+/// Most lights the renderer uploads, the rest are dropped by [`Renderer::update_lights`].
+/// Devices with small storage buffer bindings hold fewer, see [`Capabilities`]
+pub const MAX_LIGHTS: usize = 1024;
+
+/// Light without a surface, only seen through what it lights.
+///
+/// Lights are added to the sun of the sky and to emissive spheres, and are only sampled at diffuse surfaces,
+/// so mirrors don't reflect them
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Light {
+	pub kind: LightKind,
+	/// Linear radiance of a white surface that faces the light, for point and spot lights at a distance of 1
+	pub color: Vec3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum LightKind {
+	/// Infinitely far away, like the sun
+	Directional {
+		/// Points towards the light
+		direction: Vec3,
+	},
+	/// Shines in all directions, falls off with the squared distance
+	Point { position: Vec3 },
+	/// Point light that only shines into a cone
+	Spot {
+		position: Vec3,
+		/// Points along the axis of the cone
+		direction: Vec3,
+		/// Angle between the axis and the edge of the cone in degrees
+		angle: f32,
+	},
+}
+
+/// Length of the light gizmo lines on screen in pixels, see [`RenderSettings::light_gizmos`]
+const GIZMO_LENGTH: f32 = 40.0;
+const GIZMO_THICKNESS: f32 = 2.0;
+/// Size of the dot at the position of point and spot lights in pixels
+const GIZMO_DOT_SIZE: f32 = 8.0;
+/// Angle between the shaft and the head of the directional light arrows
+const GIZMO_ARROW_ANGLE: f32 = 0.5;
+
+/// Values of `Light.kind` in the shader
+const LIGHT_DIRECTIONAL: u32 = 0;
+const LIGHT_POINT: u32 = 1;
+const LIGHT_SPOT: u32 = 2;
+
+/// Layout of a [`Light`] in the light buffer, fields that don't apply to its kind are zero
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct GpuLight {
+	position: Vec3,
+	kind: u32,
+	/// Normalized
+	direction: Vec3,
+	/// Cosine of the spot light angle
+	cos_angle: f32,
+	color: Vec3,
+	_padding: u32,
+}
+
+// Layout of `Light` in shader.wgsl
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(GpuLight, kind) == 12);
+	assert!(offset_of!(GpuLight, direction) == 16);
+	assert!(offset_of!(GpuLight, cos_angle) == 28);
+	assert!(offset_of!(GpuLight, color) == 32);
+	assert!(size_of::<GpuLight>() == 48);
+};
+
+impl From<&Light> for GpuLight {
+	fn from(light: &Light) -> Self {
+		let (kind, position, direction, cos_angle) = match light.kind {
+			LightKind::Directional { direction } => (LIGHT_DIRECTIONAL, Vec3::ZERO, direction, 0.0),
+			LightKind::Point { position } => (LIGHT_POINT, position, Vec3::ZERO, 0.0),
+			LightKind::Spot {
+				position,
+				direction,
+				angle,
+			} => (LIGHT_SPOT, position, direction, angle.to_radians().cos()),
+		};
+		Self {
+			position,
+			kind,
+			direction: direction.normalize_or_zero(),
+			cos_angle,
+			color: light.color,
+			_padding: 0,
+		}
+	}
+}
+
+/// [`MAX_LIGHTS`], lowered to the lights that fit into a storage buffer binding of the device
+fn max_lights(capabilities: &Capabilities) -> usize {
+	MAX_LIGHTS.min(
+		capabilities.max_storage_buffer_binding_size as usize / std::mem::size_of::<GpuLight>(),
+	)
+}
+
+/// Half precision version of [`GpuSphere`], see [`AppConfig::packed_spheres`]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PackedSphere {
+	position_radius: [f16; 4],
+	emission_metallic: [f16; 4],
+	color: [u8; 4],
+	flags: u32,
+}
+
+// Layout of `PackedSphere` in spheres_packed.wgsl
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(PackedSphere, emission_metallic) == 8);
+	assert!(offset_of!(PackedSphere, color) == 16);
+	assert!(offset_of!(PackedSphere, flags) == 20);
+	assert!(size_of::<PackedSphere>() == 24);
+};
+
+impl From<&Sphere> for PackedSphere {
+	fn from(sphere: &Sphere) -> Self {
+		Self {
+			position_radius: sphere
+				.position
+				.extend(sphere.radius)
+				.to_array()
+				.map(f16::from_f32),
+			// Saturate instead of overflowing to infinity, which would turn lit pixels into NaN
+			emission_metallic: sphere
+				.emission
+				.clamp(
+					Vec3::splat(f16::MIN.to_f32()),
+					Vec3::splat(f16::MAX.to_f32()),
+				)
+				.extend(sphere.metallic)
+				.to_array()
+				.map(f16::from_f32),
+			color: sphere
+				.color
+				.to_array()
+				.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8),
+			flags: sphere.flags(),
+		}
+	}
 }
 
 #[repr(C)]
@@ -85,10 +802,490 @@ fn create_bind_group(
 	(bind_group_layout, bind_group)
 }
 
+/// Storage buffer entry of the objects bind group, wgpu checks that bound buffers hold at least `min_size` bytes
+fn storage_layout_entry(binding: u32, min_size: usize) -> wgpu::BindGroupLayoutEntry {
+	wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Buffer {
+			ty: wgpu::BufferBindingType::Storage { read_only: true },
+			has_dynamic_offset: false,
+			min_binding_size: wgpu::BufferSize::new(min_size as u64),
+		},
+		count: None,
+	}
+}
+
+/// Every storage buffer has to hold at least one element, the buffers get a placeholder if there are none
+fn create_objects_bind_group_layout(device: &wgpu::Device, packed: bool) -> wgpu::BindGroupLayout {
+	let sphere_size = if packed {
+		std::mem::size_of::<PackedSphere>()
+	} else {
+		std::mem::size_of::<GpuSphere>()
+	};
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Object Bind Group Layout"),
+		entries: &[
+			storage_layout_entry(0, sphere_size),
+			// The emitter count and one index
+			storage_layout_entry(1, 2 * std::mem::size_of::<u32>()),
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					view_dimension: wgpu::TextureViewDimension::D2Array,
+					multisampled: false,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 3,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+			storage_layout_entry(4, std::mem::size_of::<GpuObb>()),
+			storage_layout_entry(5, std::mem::size_of::<GpuLight>()),
+		],
+	})
+}
+
+fn create_objects_bind_group(
+	device: &wgpu::Device,
+	layout: &wgpu::BindGroupLayout,
+	spheres: &[Sphere],
+	obbs: &[Obb],
+	lights: &[Light],
+	packed: bool,
+	textures: &MaterialTextures,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+	let mut packed_spheres: Vec<PackedSphere> = Vec::new();
+	let mut gpu_spheres: Vec<GpuSphere> = Vec::new();
+	let contents = if packed {
+		packed_spheres.extend(spheres.iter().map(PackedSphere::from));
+		// Zero sized buffers can't be bound, the shader reads the real count from the settings
+		if packed_spheres.is_empty() {
+			packed_spheres.push(PackedSphere::zeroed());
+		}
+		bytemuck::cast_slice(&packed_spheres)
+	} else {
+		gpu_spheres.extend(spheres.iter().map(GpuSphere::from));
+		if gpu_spheres.is_empty() {
+			gpu_spheres.push(GpuSphere::zeroed());
+		}
+		bytemuck::cast_slice(&gpu_spheres)
+	};
+	let object_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Object Buffer"),
+		contents,
+		usage: wgpu::BufferUsages::STORAGE,
+	});
+
+	// Number of emissive spheres followed by their indices, sampled for next event estimation
+	let emitters: Vec<u32> = spheres
+		.iter()
+		.enumerate()
+		.filter(|(_, sphere)| sphere.emission != Vec3::ZERO)
+		.map(|(index, _)| index as u32)
+		.collect();
+	let mut emitter_data = [&[emitters.len() as u32], emitters.as_slice()].concat();
+	// The runtime sized array needs room for at least one element
+	if emitters.is_empty() {
+		emitter_data.push(0);
+	}
+	let emitter_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Emitter Buffer"),
+		contents: bytemuck::cast_slice(&emitter_data),
+		usage: wgpu::BufferUsages::STORAGE,
+	});
+
+	let mut gpu_obbs: Vec<GpuObb> = obbs.iter().map(GpuObb::from).collect();
+	if gpu_obbs.is_empty() {
+		gpu_obbs.push(GpuObb::zeroed());
+	}
+	let obb_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Obb Buffer"),
+		contents: bytemuck::cast_slice(&gpu_obbs),
+		usage: wgpu::BufferUsages::STORAGE,
+	});
+
+	let mut gpu_lights: Vec<GpuLight> = lights.iter().map(GpuLight::from).collect();
+	if gpu_lights.is_empty() {
+		gpu_lights.push(GpuLight::zeroed());
+	}
+	let light_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+		label: Some("Light Buffer"),
+		contents: bytemuck::cast_slice(&gpu_lights),
+		usage: wgpu::BufferUsages::STORAGE,
+	});
+
+	let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Object Bind Group"),
+		layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: object_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: emitter_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::TextureView(&textures.view),
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::Sampler(&textures.sampler),
+			},
+			wgpu::BindGroupEntry {
+				binding: 4,
+				resource: obb_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 5,
+				resource: light_buffer.as_entire_binding(),
+			},
+		],
+	});
+
+	(object_buffer, bind_group)
+}
+
+fn create_accumulation_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Accumulation Bind Group Layout"),
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Storage { read_only: false },
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			},
+			// History
+			wgpu::BindGroupLayoutEntry {
+				binding: 1,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Texture {
+					sample_type: wgpu::TextureSampleType::Float { filterable: true },
+					view_dimension: wgpu::TextureViewDimension::D2,
+					multisampled: false,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+		],
+	})
+}
+
+/// Copy of the radiance of the previous frame, see [`AppConfig::temporal_history`]
+fn create_history_texture(device: &wgpu::Device, width: u32, height: u32) -> wgpu::Texture {
+	device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("History Texture"),
+		size: wgpu::Extent3d {
+			width: width.max(1),
+			height: height.max(1),
+			depth_or_array_layers: 1,
+		},
+		mip_level_count: 1,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: HDR_FORMAT,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+		view_formats: &[],
+	})
+}
+
+/// Holds the sum of all samples and the sample count of every pixel, and the `history` of the previous frame
+fn create_accumulation_bind_group(
+	device: &wgpu::Device,
+	layout: &wgpu::BindGroupLayout,
+	history: &wgpu::Texture,
+	history_sampler: &wgpu::Sampler,
+	width: u32,
+	height: u32,
+) -> wgpu::BindGroup {
+	let accumulation_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+		label: Some("Accumulation Buffer"),
+		size: (width.max(1) * height.max(1)) as wgpu::BufferAddress
+			* std::mem::size_of::<Vec4>() as wgpu::BufferAddress,
+		usage: wgpu::BufferUsages::STORAGE,
+		mapped_at_creation: false,
+	});
+
+	device.create_bind_group(&wgpu::BindGroupDescriptor {
+		label: Some("Accumulation Bind Group"),
+		layout,
+		entries: &[
+			wgpu::BindGroupEntry {
+				binding: 0,
+				resource: accumulation_buffer.as_entire_binding(),
+			},
+			wgpu::BindGroupEntry {
+				binding: 1,
+				resource: wgpu::BindingResource::TextureView(
+					&history.create_view(&wgpu::TextureViewDescriptor::default()),
+				),
+			},
+			wgpu::BindGroupEntry {
+				binding: 2,
+				resource: wgpu::BindingResource::Sampler(history_sampler),
+			},
+		],
+	})
+}
+
+/// Format of the radiance written by the path tracing pass, before post processing
+const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+/// Format of the hit distances written by the path tracing pass, see [`Renderer::read_depth`]
+const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+/// Format of the normals written by the path tracing pass, for the outlines of [`Toon`](crate::settings::Toon)
+const NORMAL_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+#[repr(C)]
+#[repr(align(16))]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+	/// `0` if bloom is disabled
+	bloom_intensity: f32,
+	/// `1` if the target doesn't convert to sRGB by itself
+	encode_srgb: u32,
+	/// Distance to the neighbors compared for outlines in texels of the hdr texture, `0` if they are disabled
+	outline_thickness: f32,
+	/// `0` if cel shading is disabled
+	toon_bands: u32,
+	outline_color: Vec3,
+	_padding: u32,
+}
+
+// Layout of `Post` in post.wgsl
+const _: () = {
+	use std::mem::{offset_of, size_of};
+	assert!(offset_of!(PostUniform, encode_srgb) == 4);
+	assert!(offset_of!(PostUniform, outline_thickness) == 8);
+	assert!(offset_of!(PostUniform, toon_bands) == 12);
+	assert!(offset_of!(PostUniform, outline_color) == 16);
+	assert!(size_of::<PostUniform>() == 32);
+};
+
+impl PostUniform {
+	fn new(settings: &RenderSettings, format: wgpu::TextureFormat) -> Self {
+		Self {
+			bloom_intensity: if settings.bloom {
+				settings.bloom_intensity
+			} else {
+				0.0
+			},
+			encode_srgb: (!format.describe().srgb).into(),
+			// The hdr texture is smaller than the output at render scales below 1
+			outline_thickness: if settings.toon.outlines {
+				(settings.toon.outline_thickness * settings.render_scale).max(0.0)
+			} else {
+				0.0
+			},
+			toon_bands: settings.toon.bands,
+			outline_color: settings.toon.outline_color,
+			_padding: 0,
+		}
+	}
+}
+
+/// Radiance of the current frame and its bloom, read by the post processing pass
+struct HdrTarget {
+	texture: wgpu::Texture,
+	view: wgpu::TextureView,
+	/// Hit distance of every pixel, read back and by the outlines
+	depth_texture: wgpu::Texture,
+	depth_view: wgpu::TextureView,
+	/// World space normal of every pixel, zero for misses
+	normal_view: wgpu::TextureView,
+	bloom: BloomTarget,
+	bind_group: wgpu::BindGroup,
+}
+
+impl HdrTarget {
+	fn new(
+		device: &wgpu::Device,
+		layout: &wgpu::BindGroupLayout,
+		bloom: &Bloom,
+		post_buffer: &wgpu::Buffer,
+		width: u32,
+		height: u32,
+	) -> Self {
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Hdr Texture"),
+			size: wgpu::Extent3d {
+				width: width.max(1),
+				height: height.max(1),
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: HDR_FORMAT,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		let bloom_target = bloom.create_target(device, &texture, &view);
+
+		let depth_texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Depth Texture"),
+			size: texture.size(),
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: DEPTH_FORMAT,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+		let depth_view = depth_texture.create_view(&wgpu::TextureViewDescriptor::default());
+		let normal_view = device
+			.create_texture(&wgpu::TextureDescriptor {
+				label: Some("Normal Texture"),
+				size: texture.size(),
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format: NORMAL_FORMAT,
+				usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+					| wgpu::TextureUsages::TEXTURE_BINDING,
+				view_formats: &[],
+			})
+			.create_view(&wgpu::TextureViewDescriptor::default());
+
+		let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+			label: Some("Hdr Bind Group"),
+			layout,
+			entries: &[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::TextureView(&view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: wgpu::BindingResource::TextureView(bloom_target.view()),
+				},
+				wgpu::BindGroupEntry {
+					binding: 2,
+					resource: wgpu::BindingResource::Sampler(&bloom.sampler),
+				},
+				wgpu::BindGroupEntry {
+					binding: 3,
+					resource: post_buffer.as_entire_binding(),
+				},
+				wgpu::BindGroupEntry {
+					binding: 4,
+					resource: wgpu::BindingResource::TextureView(&depth_view),
+				},
+				wgpu::BindGroupEntry {
+					binding: 5,
+					resource: wgpu::BindingResource::TextureView(&normal_view),
+				},
+			],
+		});
+
+		Self {
+			texture,
+			view,
+			depth_texture,
+			depth_view,
+			normal_view,
+			bloom: bloom_target,
+			bind_group,
+		}
+	}
+}
+
+fn create_hdr_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+	let texture_entry = |binding, filterable| wgpu::BindGroupLayoutEntry {
+		binding,
+		visibility: wgpu::ShaderStages::FRAGMENT,
+		ty: wgpu::BindingType::Texture {
+			sample_type: wgpu::TextureSampleType::Float { filterable },
+			view_dimension: wgpu::TextureViewDimension::D2,
+			multisampled: false,
+		},
+		count: None,
+	};
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		label: Some("Hdr Bind Group Layout"),
+		entries: &[
+			// Radiance
+			texture_entry(0, true),
+			// Bloom
+			texture_entry(1, true),
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 3,
+				visibility: wgpu::ShaderStages::FRAGMENT,
+				ty: wgpu::BindingType::Buffer {
+					ty: wgpu::BufferBindingType::Uniform,
+					has_dynamic_offset: false,
+					min_binding_size: None,
+				},
+				count: None,
+			},
+			// Depth, 32 bit floats can't be filtered on every device
+			texture_entry(4, false),
+			// Normals
+			texture_entry(5, false),
+		],
+	})
+}
+
+fn create_render_pipeline(
+	device: &wgpu::Device,
+	label: &'static str,
+	layout: &wgpu::PipelineLayout,
+	shader: &wgpu::ShaderModule,
+	fragment_entry_point: &'static str,
+	format: wgpu::TextureFormat,
+) -> wgpu::RenderPipeline {
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		label: Some(&format!("{label} Pipeline {format:?}")),
+		layout: Some(layout),
+		vertex: wgpu::VertexState {
+			module: shader,
+			entry_point: "vs_main",
+			buffers: &[Vertex::descriptor()],
+		},
+		fragment: Some(wgpu::FragmentState {
+			module: shader,
+			entry_point: fragment_entry_point,
+			targets: &[Some(format.into())],
+		}),
+		primitive: wgpu::PrimitiveState::default(),
+		depth_stencil: None,
+		multisample: wgpu::MultisampleState::default(),
+		multiview: None,
+	})
+}
+
 fn create_shader_module(
 	device: &wgpu::Device,
 	label: &'static str,
-	source: &'static str,
+	source: &str,
 ) -> wgpu::ShaderModule {
 	device.create_shader_module(wgpu::ShaderModuleDescriptor {
 		label: Some(label),
@@ -96,36 +1293,203 @@ fn create_shader_module(
 	})
 }
 
+/// Scene and gpu memory usage of a [`Renderer`], sizes are in bytes
+#[derive(Copy, Clone, Debug)]
+pub struct RendererStats {
+	pub sphere_count: usize,
+	pub objects_buffer_size: wgpu::BufferAddress,
+	pub vertex_buffer_size: wgpu::BufferAddress,
+	pub index_buffer_size: wgpu::BufferAddress,
+	pub uniform_buffers_size: wgpu::BufferAddress,
+	/// Whether the device supports binding arrays of buffers
+	pub binding_arrays: bool,
+}
+
+impl RendererStats {
+	pub fn total_buffer_size(&self) -> wgpu::BufferAddress {
+		self.objects_buffer_size
+			+ self.vertex_buffer_size
+			+ self.index_buffer_size
+			+ self.uniform_buffers_size
+	}
+}
+
+/// Forward and up direction of the cubemap faces in layer order +x, -x, +y, -y, +z, -z,
+/// oriented so the images match the texture coordinates of a cube texture view
+const CUBE_FACES: [(Vec3, Vec3); 6] = [
+	(Vec3::X, Vec3::Y),
+	(Vec3::NEG_X, Vec3::Y),
+	(Vec3::Y, Vec3::NEG_Z),
+	(Vec3::NEG_Y, Vec3::Z),
+	(Vec3::Z, Vec3::Y),
+	(Vec3::NEG_Z, Vec3::Y),
+];
+
+/// Texture format of [`Renderer::render_offscreen`], can be sampled by e.g. egui.
+///
+/// Every target the renderer draws into ends up with sRGB encoded 8 bit colors, targets without an
+/// sRGB format are encoded by the post processing shader, so captures look the same as the window
+pub const OFFSCREEN_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Texture that is rendered into instead of a window surface
+struct OffscreenTarget {
+	texture: wgpu::Texture,
+	view: wgpu::TextureView,
+}
+
+impl OffscreenTarget {
+	fn new(device: &wgpu::Device, width: u32, height: u32) -> Self {
+		let texture = device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Offscreen Texture"),
+			size: wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: OFFSCREEN_FORMAT,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		Self { texture, view }
+	}
+}
+
+/// Path traces a scene into textures or window surfaces.
+///
+/// Nothing has to happen between frames except calling one of the render methods,
+/// which take care of accumulation, the frame index and finishing readbacks:
+///
+/// 1. create it with [`Renderer::new`], or [`Renderer::from_device`] to share an existing device
+/// 2. call [`Renderer::resize`] with the size of the target, and again whenever it changes
+/// 3. upload the scene with [`Renderer::set_scene`], or parts of it with
+///    [`Renderer::update_spheres`], [`Renderer::update_camera_transform`] and [`Renderer::update_settings`]
+/// 4. call [`Renderer::render`] or [`Renderer::render_offscreen`] once per frame
+/// 5. call [`Renderer::wait_for_captures`] before exiting if anything was captured
 pub struct Renderer {
 	pub device: wgpu::Device,
 	queue: wgpu::Queue,
-	render_pipeline: wgpu::RenderPipeline,
+	capabilities: Capabilities,
+	/// Path traces the scene into `hdr_target`
+	geometry_pipeline: wgpu::RenderPipeline,
+	post_shader: wgpu::ShaderModule,
+	post_pipeline_layout: wgpu::PipelineLayout,
+	/// Fragment entry point of the post shader, depends on [`AppConfig::premultiplied_alpha`]
+	post_entry_point: &'static str,
+	/// One post processing pipeline per texture format that has been rendered into
+	post_pipelines: HashMap<wgpu::TextureFormat, wgpu::RenderPipeline>,
+	hdr_bind_group_layout: wgpu::BindGroupLayout,
+	hdr_target: HdrTarget,
+	bloom: Bloom,
+	post_buffer: wgpu::Buffer,
+	overlay: Overlay,
+	profiler: Profiler,
+	offscreen_target: Option<OffscreenTarget>,
+	/// Captures whose readback buffers are still being mapped
+	pending_captures: Vec<PendingCapture>,
+	/// Threads writing finished captures to disk
+	saving_captures: Vec<std::thread::JoinHandle<()>>,
 	vertex_buffer: wgpu::Buffer,
 	index_buffer: wgpu::Buffer,
+	/// Copies of the spheres and boxes on the gpu, for picking and recreating the objects bind group
+	spheres: Vec<Sphere>,
+	obbs: Vec<Obb>,
+	lights: Vec<Light>,
+	packed_spheres: bool,
+	validate_spheres: bool,
+	device_polling: DevicePolling,
+	objects_buffer: wgpu::Buffer,
+	objects_bind_group_layout: wgpu::BindGroupLayout,
 	objects_bind_group: wgpu::BindGroup,
+	textures: MaterialTextures,
+	/// Size of the window or offscreen texture, the scene is traced at [`Renderer::render_size`]
+	output_size: (u32, u32),
+	/// Rectangle of the output the image is drawn into as `(x, y, width, height)`, `None` fills it
+	viewport: Option<(u32, u32, u32, u32)>,
+	camera: CameraUniform,
 	camera_buffer: wgpu::Buffer,
 	camera_bind_group: wgpu::BindGroup,
+	settings: RenderSettings,
+	settings_buffer: wgpu::Buffer,
+	settings_bind_group: wgpu::BindGroup,
+	/// Frames accumulated since the scene, camera or settings last changed
+	frame: u32,
+	accumulation_bind_group_layout: wgpu::BindGroupLayout,
+	accumulation_bind_group: wgpu::BindGroup,
+	/// Whether every frame is copied into `history`, see [`AppConfig::temporal_history`]
+	keep_history: bool,
+	/// Radiance of the previous frame, a placeholder unless `keep_history`
+	history: wgpu::Texture,
+	history_sampler: wgpu::Sampler,
+	/// Camera `history` was rendered with
+	previous_camera: CameraUniform,
+	previous_camera_buffer: wgpu::Buffer,
+	/// Whether `history` holds a frame the current camera can reproject, see [`CameraUniform::is_jump`]
+	history_valid: bool,
+	/// Frames that failed validation, only counted in debug builds
+	#[cfg(debug_assertions)]
+	validation_errors: u32,
 }
 
 impl Renderer {
 	pub async fn new(
 		adapter: wgpu::Adapter,
 		swapchain_format: wgpu::TextureFormat,
+		spheres: &[Sphere],
+		config: &AppConfig,
 	) -> anyhow::Result<Self> {
+		let capabilities = Capabilities::new(&adapter);
+		log::info!("Adapter capabilities: {capabilities}");
 		// Create the logical device and command queue
 		let (device, queue) = adapter
 			.request_device(
 				&wgpu::DeviceDescriptor {
 					label: None,
-					features: wgpu::Features::BUFFER_BINDING_ARRAY
-						| wgpu::Features::STORAGE_RESOURCE_BINDING_ARRAY,
-					// Make sure we use the texture resolution liits from the adapter, so we can support images the size of the swapchain.
-					limits: wgpu::Limits::downlevel_defaults().using_resolution(adapter.limits()),
+					// Binding arrays are only reported in the stats and timestamps only used for profiling,
+					// software adapters often lack them
+					features: capabilities.features(),
+					limits: capabilities.limits(&adapter),
 				},
 				None,
 				// Some(Path::new("./traces")),
 			)
 			.await?;
+		Self::from_device(device, queue, swapchain_format, spheres, config)
+	}
+
+	/// Renderer on a device created by the caller, e.g. to share it with other rendering code.
+	///
+	/// Only the features and limits of `device` are used, request them with the help of
+	/// [`Capabilities`] to get the same renderer as [`Renderer::new`]
+	pub fn from_device(
+		device: wgpu::Device,
+		queue: wgpu::Queue,
+		swapchain_format: wgpu::TextureFormat,
+		spheres: &[Sphere],
+		config: &AppConfig,
+	) -> anyhow::Result<Self> {
+		let capabilities = Capabilities::from_device(&device);
+		if capabilities.max_bind_groups < REQUIRED_BIND_GROUPS {
+			anyhow::bail!(
+				"The device supports {} bind groups, the renderer needs {REQUIRED_BIND_GROUPS}",
+				capabilities.max_bind_groups
+			);
+		}
+		if capabilities.max_storage_buffers_per_shader_stage < REQUIRED_STORAGE_BUFFERS {
+			anyhow::bail!(
+				"The device supports {} storage buffers per shader stage, the renderer needs {REQUIRED_STORAGE_BUFFERS}",
+				capabilities.max_storage_buffers_per_shader_stage
+			);
+		}
+		let spheres = &*checked_spheres(spheres, max_spheres(&capabilities, config.packed_spheres));
+		if config.validate_spheres {
+			warn_contained_spheres(spheres);
+		}
 
 		device.start_capture();
 
@@ -141,76 +1505,70 @@ impl Renderer {
 			usage: wgpu::BufferUsages::INDEX,
 		});
 
+		let camera = CameraUniform::new(&Camera::default(), 1, 1);
 		let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
 			label: Some("Camera Buffer"),
-			contents: bytemuck::cast_slice(&[CameraUniform {
-				width: 1,
-				height: 1,
-			}]),
+			contents: bytemuck::cast_slice(&[camera]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		// The camera the history was rendered with
+		let previous_camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Previous Camera Buffer"),
+			contents: bytemuck::cast_slice(&[camera]),
 			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
 		});
 
+		let camera_layout_entry = |binding| wgpu::BindGroupLayoutEntry {
+			binding,
+			visibility: wgpu::ShaderStages::FRAGMENT,
+			ty: wgpu::BindingType::Buffer {
+				ty: wgpu::BufferBindingType::Uniform,
+				has_dynamic_offset: false,
+				min_binding_size: None,
+			},
+			count: None,
+		};
 		let (camera_bind_group_layout, camera_bind_group) = create_bind_group(
 			&device,
 			"Camera",
-			&[wgpu::BindGroupLayoutEntry {
-				binding: 0,
-				visibility: wgpu::ShaderStages::FRAGMENT,
-				ty: wgpu::BindingType::Buffer {
-					ty: wgpu::BufferBindingType::Uniform,
-					has_dynamic_offset: false,
-					min_binding_size: None,
+			&[camera_layout_entry(0), camera_layout_entry(1)],
+			&[
+				wgpu::BindGroupEntry {
+					binding: 0,
+					resource: wgpu::BindingResource::Buffer(
+						camera_buffer.as_entire_buffer_binding(),
+					),
 				},
-				count: None,
-			}],
-			&[wgpu::BindGroupEntry {
-				binding: 0,
-				resource: wgpu::BindingResource::Buffer(camera_buffer.as_entire_buffer_binding()),
-			}],
+				wgpu::BindGroupEntry {
+					binding: 1,
+					resource: previous_camera_buffer.as_entire_binding(),
+				},
+			],
 		);
 
-		let spheres = &[
-			Sphere {
-				radius: 1.0,
-				position: Vec3::new(-1.5, 0.0, 0.5),
-				color: Vec4::new(1.0, 0.1, 0.1, 1.0),
-			},
-			Sphere {
-				radius: 0.5,
-				position: Vec3::new(-0.5, 0.0, 0.2),
-				color: Vec4::new(0.1, 1.0, 0.1, 1.0),
-			},
-			Sphere {
-				radius: 0.25,
-				position: Vec3::new(0.0, 0.0, 0.0),
-				color: Vec4::new(0.1, 0.1, 1.0, 1.0),
-			},
-			Sphere {
-				radius: 0.5,
-				position: Vec3::new(0.5, 0.0, 0.2),
-				color: Vec4::new(0.0, 1.0, 0.1, 1.0),
-			},
-			Sphere {
-				radius: 1.0,
-				position: Vec3::new(1.5, 0.0, 0.5),
-				color: Vec4::new(1.0, 0.1, 0.1, 1.0),
-			},
-		];
-
-		let object_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-			label: Some("Object Buffer"),
-			contents: bytemuck::cast_slice(spheres),
-			usage: wgpu::BufferUsages::STORAGE,
+		let settings_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Settings Buffer"),
+			contents: bytemuck::cast_slice(&[SettingsUniform::new(
+				&RenderSettings::default(),
+				0,
+				0,
+				0,
+				0,
+				0,
+				false,
+			)]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
 		});
 
-		let (objects_bind_group_layout, objects_bind_group) = create_bind_group(
+		let (settings_bind_group_layout, settings_bind_group) = create_bind_group(
 			&device,
-			"Object",
+			"Settings",
 			&[wgpu::BindGroupLayoutEntry {
 				binding: 0,
 				visibility: wgpu::ShaderStages::FRAGMENT,
 				ty: wgpu::BindingType::Buffer {
-					ty: wgpu::BufferBindingType::Storage { read_only: true },
+					ty: wgpu::BufferBindingType::Uniform,
 					has_dynamic_offset: false,
 					min_binding_size: None,
 				},
@@ -218,21 +1576,66 @@ impl Renderer {
 			}],
 			&[wgpu::BindGroupEntry {
 				binding: 0,
-				resource: object_buffer.as_entire_binding(),
+				resource: settings_buffer.as_entire_binding(),
 			}],
 		);
 
+		let history = create_history_texture(&device, 1, 1);
+		let history_sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("History Sampler"),
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			..Default::default()
+		});
+		let accumulation_bind_group_layout = create_accumulation_bind_group_layout(&device);
+		let accumulation_bind_group = create_accumulation_bind_group(
+			&device,
+			&accumulation_bind_group_layout,
+			&history,
+			&history_sampler,
+			camera.width,
+			camera.height,
+		);
+
+		let textures = MaterialTextures::new(&device);
+		let objects_bind_group_layout =
+			create_objects_bind_group_layout(&device, config.packed_spheres);
+		let (objects_buffer, objects_bind_group) = create_objects_bind_group(
+			&device,
+			&objects_bind_group_layout,
+			spheres,
+			&[],
+			&[],
+			config.packed_spheres,
+			&textures,
+		);
+
 		// Load the shaders from disk
-		let shader = create_shader_module(&device, "Screen Shader", include_str!("shader.wgsl"));
+		let sphere_storage = if config.packed_spheres {
+			include_str!("spheres_packed.wgsl")
+		} else {
+			include_str!("spheres.wgsl")
+		};
+		let limits = format!("const max_spheres = {MAX_SPHERES}u;\n");
+		let shader = create_shader_module(
+			&device,
+			"Screen Shader",
+			&[include_str!("shader.wgsl"), sphere_storage, &limits].concat(),
+		);
 
 		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-			label: Some("Render Pipeline Layout"),
-			bind_group_layouts: &[&camera_bind_group_layout, &objects_bind_group_layout],
+			label: Some("Geometry Pipeline Layout"),
+			bind_group_layouts: &[
+				&camera_bind_group_layout,
+				&objects_bind_group_layout,
+				&settings_bind_group_layout,
+				&accumulation_bind_group_layout,
+			],
 			push_constant_ranges: &[],
 		});
 
-		let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-			label: Some("Render Pipeline"),
+		let geometry_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+			label: Some("Geometry Pipeline"),
 			layout: Some(&pipeline_layout),
 			vertex: wgpu::VertexState {
 				module: &shader,
@@ -242,7 +1645,11 @@ impl Renderer {
 			fragment: Some(wgpu::FragmentState {
 				module: &shader,
 				entry_point: "fs_main",
-				targets: &[Some(swapchain_format.into())],
+				targets: &[
+					Some(HDR_FORMAT.into()),
+					Some(DEPTH_FORMAT.into()),
+					Some(NORMAL_FORMAT.into()),
+				],
 			}),
 			primitive: wgpu::PrimitiveState::default(),
 			depth_stencil: None,
@@ -250,63 +1657,1167 @@ impl Renderer {
 			multiview: None,
 		});
 
+		let bloom = Bloom::new(&device, HDR_FORMAT);
+		let post_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: Some("Post Buffer"),
+			contents: bytemuck::cast_slice(&[PostUniform::new(
+				&RenderSettings::default(),
+				swapchain_format,
+			)]),
+			usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+		});
+
+		let hdr_bind_group_layout = create_hdr_bind_group_layout(&device);
+		let hdr_target = HdrTarget::new(
+			&device,
+			&hdr_bind_group_layout,
+			&bloom,
+			&post_buffer,
+			camera.width,
+			camera.height,
+		);
+
+		let post_entry_point = if config.premultiplied_alpha {
+			"fs_premultiplied"
+		} else {
+			"fs_main"
+		};
+		let post_shader = create_shader_module(&device, "Post Shader", include_str!("post.wgsl"));
+		let post_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			label: Some("Post Pipeline Layout"),
+			bind_group_layouts: &[&hdr_bind_group_layout],
+			push_constant_ranges: &[],
+		});
+
+		let post_pipelines = HashMap::from([(
+			swapchain_format,
+			create_render_pipeline(
+				&device,
+				"Post",
+				&post_pipeline_layout,
+				&post_shader,
+				post_entry_point,
+				swapchain_format,
+			),
+		)]);
+
+		let overlay = Overlay::new(&device, &queue)?;
+		let profiler = Profiler::new(&device, &queue, capabilities.timestamp_queries);
+
 		Ok(Self {
 			device,
 			queue,
-			render_pipeline,
+			capabilities,
+			geometry_pipeline,
+			post_shader,
+			post_pipeline_layout,
+			post_entry_point,
+			post_pipelines,
+			hdr_bind_group_layout,
+			hdr_target,
+			bloom,
+			post_buffer,
+			overlay,
+			profiler,
+			offscreen_target: None,
+			pending_captures: Vec::new(),
+			saving_captures: Vec::new(),
 			vertex_buffer,
 			index_buffer,
+			spheres: spheres.to_vec(),
+			obbs: Vec::new(),
+			lights: Vec::new(),
+			packed_spheres: config.packed_spheres,
+			validate_spheres: config.validate_spheres,
+			device_polling: config.device_polling,
+			objects_buffer,
+			objects_bind_group_layout,
+			objects_bind_group,
+			textures,
+			output_size: (1, 1),
+			viewport: None,
+			camera,
 			camera_buffer,
 			camera_bind_group,
-			objects_bind_group,
+			settings: RenderSettings::default(),
+			settings_buffer,
+			settings_bind_group,
+			frame: 0,
+			accumulation_bind_group_layout,
+			accumulation_bind_group,
+			keep_history: config.temporal_history,
+			history,
+			history_sampler,
+			previous_camera: camera,
+			previous_camera_buffer,
+			history_valid: false,
+			#[cfg(debug_assertions)]
+			validation_errors: 0,
 		})
 	}
 
-	pub fn update_camera(&mut self, width: u32, height: u32) {
-		self.queue.write_buffer(
-			&self.camera_buffer,
-			0,
-			bytemuck::cast_slice(&[CameraUniform { width, height }]),
-		)
+	/// Uploads the spheres, camera and settings of `scene`
+	pub fn set_scene(&mut self, scene: &Scene) {
+		// All objects are uploaded together by update_spheres
+		self.obbs = scene.obbs.clone();
+		self.lights = checked_lights(&scene.lights, max_lights(&self.capabilities));
+		self.update_spheres(&scene.world_spheres());
+		self.update_camera_transform(&scene.camera);
+		self.update_settings(&scene.settings);
+	}
+
+	pub fn update_settings(&mut self, settings: &RenderSettings) {
+		// Post processing and the crosshair are applied to the accumulated image, changing them keeps the samples
+		let post_processing_only = RenderSettings {
+			bloom: self.settings.bloom,
+			bloom_threshold: self.settings.bloom_threshold,
+			bloom_intensity: self.settings.bloom_intensity,
+			crosshair: self.settings.crosshair,
+			toon: self.settings.toon,
+			light_gizmos: self.settings.light_gizmos,
+			..*settings
+		} == self.settings;
+		self.settings = *settings;
+		if !post_processing_only {
+			self.reset_accumulation();
+		}
+		self.resize_render_targets();
+	}
+
+	/// Discards the accumulated samples.
+	/// Changes made through the renderer already do this, call it when the image changes for other reasons.
+	pub fn reset_accumulation(&mut self) {
+		self.frame = 0;
+	}
+
+	/// Samples per pixel averaged into the last rendered frame
+	pub fn sample_count(&self) -> u32 {
+		let samples_per_pixel = self.settings.samples_per_pixel.max(1);
+		if self.settings.accumulate {
+			self.frame * samples_per_pixel
+		} else {
+			samples_per_pixel
+		}
+	}
+
+	/// Whether at least `target` samples per pixel have been accumulated
+	pub fn is_converged(&self, target: u32) -> bool {
+		self.sample_count() >= target
+	}
+
+	/// Whether captures are still waiting for their readback, which needs more calls to [`Renderer::render`]
+	pub fn has_pending_captures(&self) -> bool {
+		!self.pending_captures.is_empty()
+	}
+
+	/// Recreates everything that depends on the output size, does nothing if the size is unchanged
+	pub fn resize(&mut self, width: u32, height: u32) {
+		if (width, height) == self.output_size {
+			return;
+		}
+		self.output_size = (width, height);
+		// Recreated at the new size by the next render_offscreen
+		self.offscreen_target = None;
+		self.resize_render_targets();
+	}
+
+	/// Draws into the rectangle `(x, y, width, height)` of the output in pixels from the top left corner,
+	/// or the whole output for `None`. The camera aspect follows the viewport.
+	///
+	/// Pixels outside the viewport keep their content, so e.g. two cameras can be rendered side by side
+	/// with one renderer each. Fails for empty rectangles, parts outside the output are clipped when drawing
+	/// and nothing is drawn if the viewport lies completely outside of it.
+	pub fn set_viewport(&mut self, viewport: Option<(u32, u32, u32, u32)>) -> anyhow::Result<()> {
+		if let Some((_, _, width, height)) = viewport {
+			if width == 0 || height == 0 {
+				anyhow::bail!("The viewport {width}x{height} is empty");
+			}
+		}
+		self.viewport = viewport;
+		self.resize_render_targets();
+		Ok(())
+	}
+
+	/// Size of the viewport, or of the output if there is none
+	fn viewport_size(&self) -> (u32, u32) {
+		match self.viewport {
+			Some((_, _, width, height)) => (width, height),
+			None => self.output_size,
+		}
+	}
+
+	/// Size the scene is traced at, the viewport size scaled by [`RenderSettings::render_scale`]
+	pub fn render_size(&self) -> (u32, u32) {
+		let scale = if self.settings.render_scale > 0.0 {
+			self.settings.render_scale
+		} else {
+			1.0
+		};
+		let (width, height) = self.viewport_size();
+		let scaled = |size: u32| ((size as f32 * scale).round() as u32).max(1);
+		(scaled(width), scaled(height))
+	}
+
+	/// Recreates the textures the scene is traced into if [`Renderer::render_size`] changed
+	fn resize_render_targets(&mut self) {
+		let (width, height) = self.render_size();
+		if (width, height) == (self.camera.width, self.camera.height) {
+			return;
+		}
+		if self.keep_history {
+			self.history = create_history_texture(&self.device, width, height);
+			self.history_valid = false;
+		}
+		self.accumulation_bind_group = create_accumulation_bind_group(
+			&self.device,
+			&self.accumulation_bind_group_layout,
+			&self.history,
+			&self.history_sampler,
+			width,
+			height,
+		);
+		self.hdr_target = HdrTarget::new(
+			&self.device,
+			&self.hdr_bind_group_layout,
+			&self.bloom,
+			&self.post_buffer,
+			width,
+			height,
+		);
+		self.camera.width = width;
+		self.camera.height = height;
+		self.write_camera();
+		self.reset_accumulation();
+	}
+
+	pub fn update_camera_transform(&mut self, camera: &Camera) {
+		self.camera = CameraUniform::new(camera, self.camera.width, self.camera.height);
+		if self.previous_camera.is_jump(&self.camera, camera.distance) {
+			self.history_valid = false;
+		}
+		self.write_camera();
+		self.reset_accumulation();
+	}
+
+	fn write_camera(&self) {
+		self.queue
+			.write_buffer(&self.camera_buffer, 0, bytemuck::cast_slice(&[self.camera]))
+	}
+
+	/// Replaces all spheres on the gpu, recreating the object buffer.
+	/// Spheres past [`MAX_SPHERES`] and, in debug builds, spheres with non-finite fields are skipped with a warning.
+	pub fn update_spheres(&mut self, spheres: &[Sphere]) {
+		let max_spheres = max_spheres(&self.capabilities, self.packed_spheres);
+		let spheres = checked_spheres(spheres, max_spheres).into_owned();
+		if self.validate_spheres && spheres != self.spheres {
+			warn_contained_spheres(&spheres);
+		}
+		self.spheres = spheres;
+		self.recreate_objects();
+	}
+
+	/// Replaces all oriented boxes on the gpu, like [`Renderer::update_spheres`]
+	pub fn update_obbs(&mut self, obbs: &[Obb]) {
+		self.obbs = obbs.to_vec();
+		self.recreate_objects();
+	}
+
+	/// Replaces all lights on the gpu, like [`Renderer::update_spheres`].
+	/// Lights past [`MAX_LIGHTS`] are skipped with a warning
+	pub fn update_lights(&mut self, lights: &[Light]) {
+		self.lights = checked_lights(lights, max_lights(&self.capabilities));
+		self.recreate_objects();
+	}
+
+	fn recreate_objects(&mut self) {
+		(self.objects_buffer, self.objects_bind_group) = create_objects_bind_group(
+			&self.device,
+			&self.objects_bind_group_layout,
+			&self.spheres,
+			&self.obbs,
+			&self.lights,
+			self.packed_spheres,
+			&self.textures,
+		);
+		self.reset_accumulation();
+	}
+
+	/// Uploads `image` for spheres to reference by the returned index in [`Sphere::texture`].
+	///
+	/// Images are scaled to a fixed size, mipmapped and horizontally wrapped around the sphere,
+	/// fails once the device can't hold more textures.
+	pub fn add_texture(&mut self, image: &image::RgbaImage) -> anyhow::Result<u32> {
+		let capacity = self.textures.capacity();
+		let index = self.textures.add(&self.device, &self.queue, image)?;
+		// The array texture was recreated to make room
+		if self.textures.capacity() != capacity {
+			self.recreate_objects();
+		}
+		self.reset_accumulation();
+		Ok(index)
+	}
+
+	/// World position of the nearest sphere under the pixel `(x, y)`, or `None` for the background.
+	///
+	/// The coordinates are physical pixels from the top left corner of the output, like the cursor positions of winit,
+	/// so they don't have to be adjusted for the scale factor of the window, the render scale or the viewport.
+	pub fn pick_position(&self, x: f32, y: f32) -> Option<Vec3> {
+		let (x, y) = match self.viewport {
+			Some((left, top, _, _)) => (x - left as f32, y - top as f32),
+			None => (x, y),
+		};
+		let (width, height) = self.viewport_size();
+		let uv = Vec2::new(
+			x / width.max(1) as f32 * 2.0 - 1.0,
+			1.0 - y / height.max(1) as f32 * 2.0,
+		);
+		let origin = self.camera.position;
+		let direction = self.camera.ray_direction(uv);
+		let (near, far) = (self.camera.near, self.camera.far);
+		let spheres = self
+			.spheres
+			.iter()
+			.filter(|sphere| sphere.visible_to_camera)
+			.filter_map(|sphere| sphere.intersect(origin, direction, near, far));
+		let obbs = self
+			.obbs
+			.iter()
+			.filter(|obb| obb.visible_to_camera)
+			.filter_map(|obb| obb.intersect(origin, direction, near, far));
+		let distance = spheres.chain(obbs).min_by(f32::total_cmp)?;
+		Some(origin + direction * distance)
+	}
+
+	/// What the device was created with, detected from the adapter
+	pub fn capabilities(&self) -> Capabilities {
+		self.capabilities
+	}
+
+	pub fn stats(&self) -> RendererStats {
+		RendererStats {
+			sphere_count: self.spheres.len(),
+			objects_buffer_size: self.objects_buffer.size(),
+			vertex_buffer_size: self.vertex_buffer.size(),
+			index_buffer_size: self.index_buffer.size(),
+			uniform_buffers_size: self.camera_buffer.size()
+				+ self.previous_camera_buffer.size()
+				+ self.settings_buffer.size()
+				+ self.post_buffer.size(),
+			binding_arrays: self.capabilities.binding_arrays,
+		}
+	}
+
+	/// Renders the next frame into a texture owned by the renderer, instead of a window surface.
+	///
+	/// The texture has the size last passed to [`Renderer::resize`] and the format [`OFFSCREEN_FORMAT`],
+	/// the returned view can be registered as a native texture with egui.
+	pub fn render_offscreen(&mut self) -> &wgpu::TextureView {
+		let (width, height) = (self.output_size.0.max(1), self.output_size.1.max(1));
+		let target = match self.offscreen_target.take() {
+			Some(target)
+				if target.texture.width() == width && target.texture.height() == height =>
+			{
+				target
+			}
+			_ => OffscreenTarget::new(&self.device, width, height),
+		};
+		self.render_view(&target.view, OFFSCREEN_FORMAT, "offscreen texture");
+		&self.offscreen_target.insert(target).view
+	}
+
+	/// Renders the scene seen from `position` into the six layers of a `size` by `size` cube texture,
+	/// e.g. to bake a reflection probe. The texture has the [`OFFSCREEN_FORMAT`].
+	///
+	/// Every face is a single frame with
+	/// [`RenderSettings::samples_per_pixel`](crate::settings::RenderSettings::samples_per_pixel) samples
+	/// and without the crosshair or light gizmos, the camera and output size are restored afterwards.
+	pub fn bake_cubemap(&mut self, position: Vec3, size: u32) -> wgpu::Texture {
+		let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+			label: Some("Cubemap Texture"),
+			size: wgpu::Extent3d {
+				width: size,
+				height: size,
+				depth_or_array_layers: CUBE_FACES.len() as u32,
+			},
+			mip_level_count: 1,
+			sample_count: 1,
+			dimension: wgpu::TextureDimension::D2,
+			format: OFFSCREEN_FORMAT,
+			usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+				| wgpu::TextureUsages::TEXTURE_BINDING
+				| wgpu::TextureUsages::COPY_SRC,
+			view_formats: &[],
+		});
+
+		let (camera, output_size, viewport, crosshair, light_gizmos) = (
+			self.camera,
+			self.output_size,
+			self.viewport.take(),
+			self.settings.crosshair.enabled,
+			self.settings.light_gizmos,
+		);
+		self.settings.crosshair.enabled = false;
+		self.settings.light_gizmos = false;
+		self.resize(size, size);
+		self.resize_render_targets();
+		for (layer, (forward, up)) in CUBE_FACES.into_iter().enumerate() {
+			self.camera = CameraUniform {
+				position,
+				forward,
+				up,
+				fov: std::f32::consts::FRAC_PI_2,
+				tile: FULL_TILE,
+				..self.camera
+			};
+			self.write_camera();
+			self.reset_accumulation();
+			let view = texture.create_view(&wgpu::TextureViewDescriptor {
+				label: Some("Cubemap Face View"),
+				dimension: Some(wgpu::TextureViewDimension::D2),
+				base_array_layer: layer as u32,
+				array_layer_count: std::num::NonZeroU32::new(1),
+				..Default::default()
+			});
+			self.render_view(&view, OFFSCREEN_FORMAT, "cubemap");
+		}
+
+		self.settings.crosshair.enabled = crosshair;
+		self.settings.light_gizmos = light_gizmos;
+		self.viewport = viewport;
+		self.resize(output_size.0, output_size.1);
+		self.resize_render_targets();
+		self.camera = camera;
+		self.write_camera();
+		self.reset_accumulation();
+		// The history shows a different view now
+		self.history_valid = false;
+		texture
+	}
+
+	/// Renders the rectangle `(x, y, width, height)` in pixels from the top left corner of an image of `full_size`,
+	/// accumulating `frames` frames, and reads it back.
+	///
+	/// Tiles of the same image line up seamlessly, so images larger than the device can render at once
+	/// can be stitched together from tiles that aren't. Bloom only spreads within a tile, turn it off for such images.
+	/// Like [`Renderer::bake_cubemap`] the crosshair and light gizmos are hidden and the camera and output size are restored afterwards
+	pub fn render_tile(
+		&mut self,
+		full_size: (u32, u32),
+		(x, y, width, height): (u32, u32, u32, u32),
+		frames: u32,
+	) -> anyhow::Result<image::RgbaImage> {
+		let (camera, output_size, viewport, crosshair, light_gizmos) = (
+			self.camera,
+			self.output_size,
+			self.viewport.take(),
+			self.settings.crosshair.enabled,
+			self.settings.light_gizmos,
+		);
+		self.settings.crosshair.enabled = false;
+		self.settings.light_gizmos = false;
+		self.resize(width, height);
+		self.resize_render_targets();
+
+		let full_size = Vec2::new(full_size.0 as f32, full_size.1 as f32);
+		let center = (Vec2::new(x as f32, y as f32) + Vec2::new(width as f32, height as f32) / 2.0)
+			/ full_size
+			* 2.0 - 1.0;
+		let size = Vec2::new(width as f32, height as f32) / full_size;
+		// uv coordinates point up, pixels down
+		self.camera.tile = Vec4::new(center.x, -center.y, size.x, size.y);
+		self.write_camera();
+		self.reset_accumulation();
+		for _ in 1..frames {
+			self.render_offscreen();
+		}
+		self.render_offscreen();
+		let Some(target) = &self.offscreen_target else {
+			unreachable!("render_offscreen should have created the offscreen target");
+		};
+		let pixels = self.read_texture(&target.texture);
+
+		self.settings.crosshair.enabled = crosshair;
+		self.settings.light_gizmos = light_gizmos;
+		self.viewport = viewport;
+		self.resize(output_size.0, output_size.1);
+		self.resize_render_targets();
+		self.camera = camera;
+		self.write_camera();
+		self.reset_accumulation();
+		// The history shows a different view now
+		self.history_valid = false;
+
+		image::RgbaImage::from_raw(width, height, pixels?)
+			.context("Tile readback has the wrong size")
+	}
+
+	/// Saves the next frame as an sRGB encoded png at `path`, without waiting for the gpu to finish.
+	///
+	/// The frame is read back asynchronously and written to disk a few calls to [`Renderer::render`] later.
+	pub fn capture(&mut self, path: impl Into<PathBuf>) {
+		self.render_offscreen();
+		let Some(target) = &self.offscreen_target else {
+			unreachable!("render_offscreen should have created the offscreen target");
+		};
+		let capture = self.record_capture(&target.texture, path.into());
+		self.pending_captures.push(capture);
+	}
+
+	/// Like [`Renderer::capture`], but saves the linear radiance before post processing as an exr file.
+	///
+	/// The image has the [`Renderer::render_size`] and contains neither bloom nor the overlay
+	pub fn capture_hdr(&mut self, path: impl Into<PathBuf>) {
+		self.render_offscreen();
+		let capture = self.record_capture(&self.hdr_target.texture, path.into());
+		self.pending_captures.push(capture);
+	}
+
+	fn record_capture(&self, texture: &wgpu::Texture, path: PathBuf) -> PendingCapture {
+		let mut encoder = self
+			.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("Capture Encoder"),
+			});
+		let mut capture = PendingCapture::new(&self.device, &mut encoder, texture, path);
+		self.queue.submit(std::iter::once(encoder.finish()));
+		capture.map();
+		capture
+	}
+
+	/// Distance from the camera to the nearest visible surface through the center of every pixel of the last frame,
+	/// the far plane of the camera where the sky is visible.
+	///
+	/// Rows go from top to bottom at the [`Renderer::render_size`]. Blocks until the gpu finished the frame
+	pub fn read_depth(&mut self) -> anyhow::Result<Vec<f32>> {
+		let data = self.read_texture(&self.hdr_target.depth_texture)?;
+		Ok(data
+			.chunks_exact(4)
+			.map(|bytes| f32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+			.collect())
+	}
+
+	/// Saves [`Renderer::read_depth`] as an exr file with the distance in the color channels
+	pub fn capture_depth(&mut self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+		let path = path.as_ref();
+		let texture = &self.hdr_target.depth_texture;
+		let (width, height) = (texture.width(), texture.height());
+		let pixels = self
+			.read_depth()?
+			.into_iter()
+			.flat_map(|depth| [depth, depth, depth, 1.0])
+			.collect();
+		image::Rgba32FImage::from_raw(width, height, pixels)
+			.context("Depth readback has the wrong size")?
+			.save_with_format(path, image::ImageFormat::OpenExr)
+			.with_context(|| format!("Failed to save {}", path.display()))?;
+		println!("Saved depth to {}", path.display());
+		Ok(())
+	}
+
+	/// Renders the next frame like [`Renderer::capture`] and reads back the rectangle `(x, y, width, height)`
+	/// in pixels from its top left corner, for inspecting pixels without copying the whole frame.
+	///
+	/// Blocks until the gpu finished the frame, fails if the rectangle is empty or reaches past the output size
+	pub fn read_region(
+		&mut self,
+		(x, y, width, height): (u32, u32, u32, u32),
+	) -> anyhow::Result<image::RgbaImage> {
+		let (output_width, output_height) = self.output_size;
+		let fits = |start: u32, size: u32, limit: u32| {
+			size > 0 && start.checked_add(size).is_some_and(|end| end <= limit)
+		};
+		if !fits(x, width, output_width) || !fits(y, height, output_height) {
+			anyhow::bail!(
+				"Region {width}x{height} at ({x}, {y}) is outside of the {output_width}x{output_height} output"
+			);
+		}
+		self.render_offscreen();
+		let Some(target) = &self.offscreen_target else {
+			unreachable!("render_offscreen should have created the offscreen target");
+		};
+		let pixels = self.read_texture_region(&target.texture, (x, y), (width, height))?;
+		image::RgbaImage::from_raw(width, height, pixels)
+			.context("Region readback has the wrong size")
+	}
+
+	/// Tightly packed texels of `texture`, blocks until the gpu finished the last frame
+	fn read_texture(&self, texture: &wgpu::Texture) -> anyhow::Result<Vec<u8>> {
+		let size = (texture.width(), texture.height());
+		self.read_texture_region(texture, (0, 0), size)
+	}
+
+	/// Like [`Renderer::read_texture`], but only the texels in the rectangle at `origin`
+	fn read_texture_region(
+		&self,
+		texture: &wgpu::Texture,
+		(x, y): (u32, u32),
+		(width, height): (u32, u32),
+	) -> anyhow::Result<Vec<u8>> {
+		let bytes_per_row = width * texture.format().describe().block_size as u32;
+		// Rows of the copy destination have to be aligned
+		let padded_bytes_per_row = bytes_per_row.div_ceil(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT)
+			* wgpu::COPY_BYTES_PER_ROW_ALIGNMENT;
+		let buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+			label: Some("Readback Buffer"),
+			size: (padded_bytes_per_row * height) as wgpu::BufferAddress,
+			usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+			mapped_at_creation: false,
+		});
+
+		let mut encoder = self
+			.device
+			.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("Readback Encoder"),
+			});
+		encoder.copy_texture_to_buffer(
+			wgpu::ImageCopyTexture {
+				origin: wgpu::Origin3d { x, y, z: 0 },
+				..texture.as_image_copy()
+			},
+			wgpu::ImageCopyBuffer {
+				buffer: &buffer,
+				layout: wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: std::num::NonZeroU32::new(padded_bytes_per_row),
+					rows_per_image: None,
+				},
+			},
+			wgpu::Extent3d {
+				width,
+				height,
+				depth_or_array_layers: 1,
+			},
+		);
+		self.queue.submit(std::iter::once(encoder.finish()));
+
+		let (sender, receiver) = mpsc::channel();
+		buffer
+			.slice(..)
+			.map_async(wgpu::MapMode::Read, move |result| {
+				let _ = sender.send(result);
+			});
+		self.device.poll(wgpu::Maintain::Wait);
+		receiver
+			.recv()
+			.context("Readback buffer was never mapped")?
+			.context("Failed to map readback buffer")?;
+
+		let data = buffer.slice(..).get_mapped_range();
+		Ok(data
+			.chunks_exact(padded_bytes_per_row as usize)
+			.flat_map(|row| &row[..bytes_per_row as usize])
+			.copied()
+			.collect())
+	}
+
+	/// Lets mapping callbacks run and writes out captures whose readback finished,
+	/// without blocking on the ones that did not
+	fn poll_device(&mut self) {
+		self.saving_captures.retain(|handle| !handle.is_finished());
+		if self.pending_captures.is_empty()
+			&& !self.profiler.is_reading_back()
+			&& self.device_polling == DevicePolling::OnDemand
+		{
+			return;
+		}
+		self.device.poll(wgpu::Maintain::Poll);
+		self.profiler.try_read();
+		let saving = &mut self.saving_captures;
+		self.pending_captures
+			.retain(|capture| !capture.try_finish(saving));
+	}
+
+	/// Blocks until the gpu finished all submitted frames
+	pub fn wait_for_gpu(&self) {
+		self.device.poll(wgpu::Maintain::Wait);
+	}
+
+	/// Blocks until every capture has been written to disk
+	pub fn wait_for_captures(&mut self) {
+		while !self.pending_captures.is_empty() {
+			self.device.poll(wgpu::Maintain::Wait);
+			let saving = &mut self.saving_captures;
+			self.pending_captures
+				.retain(|capture| !capture.try_finish(saving));
+		}
+		for handle in self.saving_captures.drain(..) {
+			if handle.join().is_err() {
+				eprintln!("Thread saving a capture panicked");
+			}
+		}
+	}
+
+	/// Gpu time in milliseconds of each render pass of a recent frame,
+	/// empty if the device doesn't support timestamp queries
+	pub fn pass_timings(&self) -> &BTreeMap<&'static str, f32> {
+		self.profiler.timings()
+	}
+
+	/// Draws `lines` of text in the top left corner of the next frame,
+	/// the lines of multiple calls before that frame are drawn below each other
+	pub fn draw_text(&mut self, lines: &[String]) {
+		self.overlay.push_lines(lines);
 	}
 
 	/// Renders the next frame into the provided [`wgpu::Texture`]
 	pub fn render(&mut self, texture: &wgpu::Texture) {
 		let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+		self.render_view(&view, texture.format(), "texture");
+	}
+
+	/// Draws the lights over the next frame, see [`RenderSettings::light_gizmos`]
+	fn push_light_gizmos(&mut self) {
+		let camera = self.camera;
+		let (left, top, width, height) =
+			self.viewport
+				.unwrap_or((0, 0, self.output_size.0, self.output_size.1));
+		let to_pixel = |point: Vec3| {
+			camera.project(point).map(|uv| {
+				let size = Vec2::new(width as f32, height as f32);
+				Vec2::new(left as f32, top as f32) + (Vec2::new(uv.x, -uv.y) + 1.0) / 2.0 * size
+			})
+		};
+		// World length that is GIZMO_LENGTH pixels long on screen at the depth of point,
+		// so gizmos keep their size at any distance
+		let world_length = |point: Vec3| {
+			let depth = (point - camera.position).dot(camera.forward);
+			depth * (camera.fov / 2.0).tan() * 2.0 * GIZMO_LENGTH / height.max(1) as f32
+		};
+		// Axis to rotate around to tilt direction sideways on screen
+		let screen_normal = |position: Vec3, direction: Vec3| {
+			let side = direction
+				.cross(position - camera.position)
+				.try_normalize()
+				.unwrap_or_else(|| direction.any_orthonormal_vector());
+			direction.cross(side)
+		};
+
+		for light in &self.lights {
+			// Light colors can be far above 1, scale the brightest channel to 1
+			let color = light.color / light.color.max_element().max(f32::EPSILON);
+			let mut segment = |from: Vec3, to: Vec3, thickness: f32| {
+				if let (Some(from), Some(to)) = (to_pixel(from), to_pixel(to)) {
+					self.overlay.push_segment(from, to, thickness, color);
+				}
+			};
+			match light.kind {
+				LightKind::Point { position } => segment(position, position, GIZMO_DOT_SIZE),
+				LightKind::Spot {
+					position,
+					direction,
+					angle,
+				} => {
+					segment(position, position, GIZMO_DOT_SIZE);
+					let direction = direction.normalize_or_zero();
+					let length = world_length(position);
+					let axis = screen_normal(position, direction);
+					let [first, second] = [-angle, angle].map(|angle| {
+						position
+							+ Quat::from_axis_angle(axis, angle.to_radians()) * direction * length
+					});
+					segment(position, first, GIZMO_THICKNESS);
+					segment(position, second, GIZMO_THICKNESS);
+					segment(first, second, GIZMO_THICKNESS);
+				}
+				LightKind::Directional { direction } => {
+					// An arrow in front of the camera along which the light travels
+					let anchor = camera.position + camera.forward;
+					let travel = -direction.normalize_or_zero();
+					let length = world_length(anchor);
+					let tip = anchor + travel * length * 0.5;
+					segment(anchor - travel * length * 0.5, tip, GIZMO_THICKNESS);
+					let axis = screen_normal(anchor, travel);
+					for angle in [-GIZMO_ARROW_ANGLE, GIZMO_ARROW_ANGLE] {
+						let head = Quat::from_axis_angle(axis, angle) * -travel * length * 0.3;
+						segment(tip, tip + head, GIZMO_THICKNESS);
+					}
+				}
+			}
+		}
+	}
+
+	/// Renders a frame into `view`, `target` names it in logged validation errors
+	#[cfg_attr(not(debug_assertions), allow(unused_variables))]
+	fn render_view(&mut self, view: &wgpu::TextureView, format: wgpu::TextureFormat, target: &str) {
+		// Debug builds log validation errors instead of letting wgpu panic on them
+		if CATCH_VALIDATION_ERRORS {
+			self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+		}
+		#[cfg(debug_assertions)]
+		let frame = self.frame;
+
+		self.queue.write_buffer(
+			&self.settings_buffer,
+			0,
+			bytemuck::cast_slice(&[SettingsUniform::new(
+				&self.settings,
+				self.frame,
+				self.textures.count(),
+				self.spheres.len() as u32,
+				self.obbs.len() as u32,
+				self.lights.len() as u32,
+				self.history_valid,
+			)]),
+		);
+		self.queue.write_buffer(
+			&self.previous_camera_buffer,
+			0,
+			bytemuck::cast_slice(&[self.previous_camera]),
+		);
+		self.queue.write_buffer(
+			&self.post_buffer,
+			0,
+			bytemuck::cast_slice(&[PostUniform::new(&self.settings, format)]),
+		);
+		if self.settings.accumulate {
+			self.frame += 1;
+		}
+
+		// Clamped to the target, which the scissor rectangle has to stay inside of
+		let viewport = self.viewport.map(|(x, y, width, height)| {
+			let (x, y) = (x.min(self.output_size.0), y.min(self.output_size.1));
+			(
+				x,
+				y,
+				width.min(self.output_size.0 - x),
+				height.min(self.output_size.1 - y),
+			)
+		});
+		// Nothing is drawn if the viewport was clipped away by a smaller output, empty viewports fail validation
+		let viewport_visible = !matches!(viewport, Some((_, _, 0, _) | (_, _, _, 0)));
+		let post_pipeline = self.post_pipelines.entry(format).or_insert_with(|| {
+			create_render_pipeline(
+				&self.device,
+				"Post",
+				&self.post_pipeline_layout,
+				&self.post_shader,
+				self.post_entry_point,
+				format,
+			)
+		});
+
 		let mut encoder = self
 			.device
 			.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+		self.profiler.begin(&mut encoder);
+
+		{
+			let mut geometry_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+				label: Some("Geometry Pass"),
+				color_attachments: &[
+					Some(wgpu::RenderPassColorAttachment {
+						view: &self.hdr_target.view,
+						resolve_target: None,
+						ops: wgpu::Operations {
+							load: wgpu::LoadOp::Clear(wgpu::Color {
+								r: 1.0,
+								g: 0.0,
+								b: 1.0,
+								a: 1.0,
+							}),
+							store: true,
+						},
+					}),
+					Some(wgpu::RenderPassColorAttachment {
+						view: &self.hdr_target.depth_view,
+						resolve_target: None,
+						ops: wgpu::Operations {
+							// Every pixel gets overwritten
+							load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+							store: true,
+						},
+					}),
+					Some(wgpu::RenderPassColorAttachment {
+						view: &self.hdr_target.normal_view,
+						resolve_target: None,
+						ops: wgpu::Operations {
+							load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+							store: true,
+						},
+					}),
+				],
+				depth_stencil_attachment: None,
+			});
+
+			geometry_pass.set_pipeline(&self.geometry_pipeline);
+
+			geometry_pass.set_bind_group(0, &self.camera_bind_group, &[]);
+			geometry_pass.set_bind_group(1, &self.objects_bind_group, &[]);
+			geometry_pass.set_bind_group(2, &self.settings_bind_group, &[]);
+			geometry_pass.set_bind_group(3, &self.accumulation_bind_group, &[]);
+
+			geometry_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+			geometry_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+
+			geometry_pass.draw_indexed(0..(QUAD_INDICES.len() as u32), 0, 0..1)
+		}
+		self.profiler.end_scope(&mut encoder, "Geometry");
+
+		if self.keep_history {
+			encoder.copy_texture_to_texture(
+				self.hdr_target.texture.as_image_copy(),
+				self.history.as_image_copy(),
+				self.history.size(),
+			);
+			self.previous_camera = self.camera;
+			self.history_valid = true;
+		}
+
+		if self.settings.bloom {
+			self.bloom.render(
+				&self.queue,
+				&mut encoder,
+				&self.hdr_target.bloom,
+				self.settings.bloom_threshold,
+			);
+			self.profiler.end_scope(&mut encoder, "Bloom");
+		}
 
 		{
-			let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-				label: Some("Render Pass"),
+			let mut post_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+				label: Some("Post Pass"),
 				color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-					view: &view,
+					view,
 					resolve_target: None,
 					ops: wgpu::Operations {
-						load: wgpu::LoadOp::Clear(wgpu::Color {
-							r: 1.0,
-							g: 0.0,
-							b: 1.0,
-							a: 1.0,
-						}),
+						// Every pixel in the viewport gets overwritten, the ones outside of it are kept
+						load: if viewport.is_some() {
+							wgpu::LoadOp::Load
+						} else {
+							wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+						},
 						store: true,
 					},
 				})],
 				depth_stencil_attachment: None,
 			});
 
-			render_pass.set_pipeline(&self.render_pipeline);
+			if viewport_visible {
+				if let Some((x, y, width, height)) = viewport {
+					post_pass.set_viewport(
+						x as f32,
+						y as f32,
+						width as f32,
+						height as f32,
+						0.0,
+						1.0,
+					);
+					post_pass.set_scissor_rect(x, y, width, height);
+				}
+				post_pass.set_pipeline(post_pipeline);
+				post_pass.set_bind_group(0, &self.hdr_target.bind_group, &[]);
 
-			render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-			render_pass.set_bind_group(1, &self.objects_bind_group, &[]);
+				post_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+				post_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
 
-			render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
-			render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint16);
+				post_pass.draw_indexed(0..(QUAD_INDICES.len() as u32), 0, 0..1)
+			}
+		}
+		self.profiler.end_scope(&mut encoder, "Post");
 
-			render_pass.draw_indexed(0..(QUAD_INDICES.len() as u32), 0, 0..1)
+		if self.settings.crosshair.enabled {
+			self.overlay.push_crosshair(self.settings.crosshair);
+		}
+		if self.settings.light_gizmos {
+			self.push_light_gizmos();
 		}
+		self.overlay.render(
+			&self.device,
+			&self.queue,
+			&mut encoder,
+			view,
+			format,
+			self.output_size,
+		);
+		self.profiler.end_scope(&mut encoder, "Overlay");
+		self.profiler.finish(&mut encoder);
 
 		self.queue.submit(std::iter::once(encoder.finish()));
+		self.profiler.map();
+
+		#[cfg(debug_assertions)]
+		if CATCH_VALIDATION_ERRORS {
+			if let Some(err) = pollster::block_on(self.device.pop_error_scope()) {
+				log::error!(
+					"Validation error while rendering frame {frame} into the {target}: {err}"
+				);
+				self.validation_errors += 1;
+			}
+		}
+		self.poll_device();
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Renderer with an empty scene on the software fallback adapter, drawing into [`Renderer::render_offscreen`].
+	/// `None` if not even a software adapter is available, the test should return early then
+	fn headless_renderer() -> Option<Renderer> {
+		// Dropping a GL instance terminates the EGL display that every other instance in the process uses,
+		// so the tests share one that lives until the end
+		static INSTANCE: std::sync::OnceLock<wgpu::Instance> = std::sync::OnceLock::new();
+		let instance =
+			INSTANCE.get_or_init(|| wgpu::Instance::new(wgpu::InstanceDescriptor::default()));
+		let Some(adapter) =
+			pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+				power_preference: wgpu::PowerPreference::default(),
+				force_fallback_adapter: true,
+				compatible_surface: None,
+			}))
+		else {
+			eprintln!("Skipping the test, no software adapter available, install e.g. llvmpipe or lavapipe");
+			return None;
+		};
+		let capabilities = Capabilities::new(&adapter);
+		let (device, queue) = pollster::block_on(adapter.request_device(
+			&wgpu::DeviceDescriptor {
+				label: Some("Test Device"),
+				features: capabilities.features(),
+				limits: capabilities.limits(&adapter),
+			},
+			None,
+		))
+		.expect("The fallback adapter has no device");
+		let renderer =
+			Renderer::from_device(device, queue, OFFSCREEN_FORMAT, &[], &AppConfig::default())
+				.expect("Failed to create the renderer");
+		Some(renderer)
+	}
+
+	/// White diffuse sphere
+	fn sphere(position: Vec3, radius: f32) -> Sphere {
+		Sphere {
+			position,
+			radius,
+			color: Vec4::ONE,
+			emission: Vec3::ZERO,
+			metallic: 0.0,
+			texture: None,
+			emissive_texture: None,
+			casts_shadow: true,
+			visible_to_camera: true,
+			max_bounces: None,
+		}
+	}
+
+	#[test]
+	fn texture_indices_past_the_flag_bits_are_untextured() {
+		let textured = |texture| Sphere {
+			texture: Some(texture),
+			emissive_texture: Some(texture),
+			..sphere(Vec3::ZERO, 1.0)
+		};
+		let untextured = sphere(Vec3::ZERO, 1.0).flags();
+		assert_ne!(textured(MAX_TEXTURES - 1).flags(), untextured);
+		assert_eq!(textured(MAX_TEXTURES).flags(), untextured);
+		assert_eq!(textured(300).flags(), untextured);
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	fn finite_spheres_drops_nan_spheres() {
+		let nan = sphere(Vec3::new(f32::NAN, 0.0, 0.0), 1.0);
+		let spheres = [sphere(Vec3::ZERO, 1.0), nan, sphere(Vec3::X, f32::INFINITY)];
+		assert_eq!(*finite_spheres(&spheres), [spheres[0]]);
+		assert!(matches!(finite_spheres(&spheres[..1]), Cow::Borrowed(_)));
+	}
+
+	#[test]
+	fn headless_renderer_reads_back_frames() {
+		let Some(mut renderer) = headless_renderer() else {
+			return;
+		};
+		renderer.resize(16, 8);
+		let image = renderer.read_region((0, 0, 16, 8)).unwrap();
+		assert_eq!(image.dimensions(), (16, 8));
+	}
+
+	#[test]
+	fn resize_discards_accumulated_samples() {
+		let (Some(mut resized), Some(mut fresh)) = (headless_renderer(), headless_renderer())
+		else {
+			return;
+		};
+		let scene = Scene::example();
+		resized.set_scene(&scene);
+		fresh.set_scene(&scene);
+
+		resized.resize(48, 32);
+		for _ in 0..4 {
+			resized.render_offscreen();
+		}
+		resized.resize(32, 24);
+		fresh.resize(32, 24);
+		// Both take the first sample with the same seed, samples from before the resize would show up as a difference
+		let image = resized.read_region((0, 0, 32, 24)).unwrap();
+		assert_eq!(resized.sample_count(), 1);
+		assert!(image == fresh.read_region((0, 0, 32, 24)).unwrap());
+	}
+
+	#[test]
+	fn empty_scene_renders_the_sky() {
+		let Some(mut renderer) = headless_renderer() else {
+			return;
+		};
+		let mut scene = Scene::new();
+		let sky = Vec3::new(0.2, 0.4, 0.6);
+		scene.settings.sky.zenith = sky;
+		scene.settings.sky.horizon = sky;
+		renderer.set_scene(&scene);
+		renderer.resize(16, 16);
+
+		let expected = color::linear_to_srgb(sky.extend(1.0)) * 255.0;
+		let image = renderer.read_region((0, 0, 16, 16)).unwrap();
+		for pixel in image.pixels() {
+			let pixel = Vec4::from(pixel.0.map(f32::from));
+			assert!(
+				pixel.abs_diff_eq(expected, 1.5),
+				"{pixel} instead of the sky {expected}"
+			);
+		}
+	}
+
+	#[test]
+	#[cfg(debug_assertions)]
+	fn validation_errors_are_caught() {
+		let Some(mut renderer) = headless_renderer() else {
+			return;
+		};
+		// The geometry pipeline expects the settings uniform in group 2
+		let layout = renderer
+			.device
+			.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+				label: None,
+				entries: &[],
+			});
+		renderer.settings_bind_group =
+			renderer
+				.device
+				.create_bind_group(&wgpu::BindGroupDescriptor {
+					label: None,
+					layout: &layout,
+					entries: &[],
+				});
+		renderer.resize(8, 8);
+		renderer.render_offscreen();
+		assert_eq!(renderer.validation_errors, 1);
+	}
+
+	#[test]
+	fn empty_viewports() {
+		let Some(mut renderer) = headless_renderer() else {
+			return;
+		};
+		renderer.resize(16, 16);
+		assert!(renderer.set_viewport(Some((0, 0, 0, 0))).is_err());
+		assert!(renderer.set_viewport(Some((4, 4, 8, 0))).is_err());
+		// Clipped away completely once the output shrinks
+		renderer.set_viewport(Some((12, 12, 4, 4))).unwrap();
+		renderer.resize(8, 8);
+		renderer.render_offscreen();
+		#[cfg(debug_assertions)]
+		assert_eq!(renderer.validation_errors, 0);
 	}
 }
@@ -0,0 +1,509 @@
+use anyhow::Context;
+use glam::{Quat, Vec3, Vec4};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::camera::Camera;
+use crate::renderer::{Light, Obb, Sphere};
+use crate::settings::RenderSettings;
+
+/// Everything needed to render an image.
+///
+/// Build it with [`Scene::new`] and [`Scene::add_sphere`], [`Scene::add_obb`] or [`Scene::add_light`],
+/// then upload it with [`Renderer::set_scene`](crate::renderer::Renderer::set_scene) before calling `render`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Scene {
+	/// Scene files written before the camera was serialized fall back to the default viewpoint
+	#[serde(default)]
+	pub camera: Camera,
+	#[serde(default)]
+	pub settings: RenderSettings,
+	#[serde(default)]
+	pub spheres: Vec<Sphere>,
+	/// Spheres that are moved and rotated together, in addition to `spheres`
+	#[serde(default)]
+	pub groups: Vec<Group>,
+	#[serde(default)]
+	pub obbs: Vec<Obb>,
+	/// In addition to the sun of the sky and emissive objects
+	#[serde(default)]
+	pub lights: Vec<Light>,
+	/// Spheres of a point cloud loaded with [`load_points`](crate::points::load_points),
+	/// not saved with the scene because they can be hundreds of thousands
+	#[serde(skip)]
+	pub points: Vec<Sphere>,
+}
+
+/// Spheres and nested groups that share a transform
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Group {
+	/// Position of the group origin in its parent
+	pub translation: Vec3,
+	/// Rotation of the children around the group origin
+	pub rotation: Quat,
+	/// Positions are relative to the group origin
+	pub spheres: Vec<Sphere>,
+	pub groups: Vec<Group>,
+}
+
+impl Group {
+	/// Appends the spheres of this group and all nested groups to `spheres`,
+	/// moved from group space into the space `transform` maps to
+	fn flatten_into(&self, (translation, rotation): (Vec3, Quat), spheres: &mut Vec<Sphere>) {
+		let transform = (
+			translation + rotation * self.translation,
+			rotation * self.rotation,
+		);
+		spheres.extend(self.spheres.iter().map(|sphere| Sphere {
+			position: transform.0 + transform.1 * sphere.position,
+			..*sphere
+		}));
+		for group in &self.groups {
+			group.flatten_into(transform, spheres);
+		}
+	}
+}
+
+impl Scene {
+	/// Empty scene with the default camera and settings
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn add_sphere(&mut self, sphere: Sphere) -> &mut Self {
+		self.spheres.push(sphere);
+		self
+	}
+
+	pub fn add_group(&mut self, group: Group) -> &mut Self {
+		self.groups.push(group);
+		self
+	}
+
+	pub fn add_obb(&mut self, obb: Obb) -> &mut Self {
+		self.obbs.push(obb);
+		self
+	}
+
+	pub fn add_light(&mut self, light: Light) -> &mut Self {
+		self.lights.push(light);
+		self
+	}
+
+	/// `spheres` followed by the spheres of every group with the group transforms applied and the `points`,
+	/// this is what gets uploaded to the gpu
+	pub fn world_spheres(&self) -> Vec<Sphere> {
+		let mut spheres = self.spheres.clone();
+		for group in &self.groups {
+			group.flatten_into((Vec3::ZERO, Quat::IDENTITY), &mut spheres);
+		}
+		spheres.extend_from_slice(&self.points);
+		spheres
+	}
+
+	/// Empty scene with `points`, looked at from far enough away to see all of them
+	pub fn from_points(points: Vec<Sphere>) -> Self {
+		let mut scene = Self::new();
+		if let Some((center, radius)) = crate::points::bounds(&points) {
+			scene.camera.frame(center, radius);
+		}
+		scene.points = points;
+		scene
+	}
+
+	/// Hash of everything that changes the rendered image, the same across runs and platforms,
+	/// so it can be stored next to a render to find out whether the scene changed since.
+	///
+	/// Groups are hashed as the [`Scene::world_spheres`] they produce. Textures are only hashed by their index
+	pub fn scene_hash(&self) -> u64 {
+		let state = RenderState {
+			camera: &self.camera,
+			settings: &self.settings,
+			spheres: self.world_spheres(),
+			obbs: &self.obbs,
+			lights: &self.lights,
+		};
+		let mut hasher = Fnv1a::default();
+		ron::ser::to_writer(&mut hasher, &state).expect("Scenes can always be serialized");
+		hasher.0
+	}
+
+	pub fn set_camera(&mut self, camera: Camera) -> &mut Self {
+		self.camera = camera;
+		self
+	}
+
+	pub fn set_settings(&mut self, settings: RenderSettings) -> &mut Self {
+		self.settings = settings;
+		self
+	}
+
+	/// Scene shown when no scene file is loaded
+	pub fn example() -> Self {
+		Self {
+			camera: Camera::default(),
+			settings: RenderSettings::default(),
+			spheres: vec![
+				// Lights the spheres from above without showing up in the image
+				Sphere {
+					radius: 1.0,
+					position: Vec3::new(0.0, 4.0, 0.0),
+					color: Vec4::new(0.0, 0.0, 0.0, 1.0),
+					emission: Vec3::splat(4.0),
+					metallic: 0.0,
+					texture: None,
+					emissive_texture: None,
+					casts_shadow: false,
+					visible_to_camera: false,
+					max_bounces: None,
+				},
+			],
+			// The five spheres in the foreground, moving or rotating the group moves all of them
+			groups: vec![Group {
+				translation: Vec3::new(0.0, 0.0, 0.2),
+				rotation: Quat::IDENTITY,
+				spheres: vec![
+					Sphere {
+						radius: 1.0,
+						position: Vec3::new(-1.5, 0.0, 0.3),
+						color: Vec4::new(1.0, 0.1, 0.1, 1.0),
+						emission: Vec3::ZERO,
+						metallic: 0.0,
+						texture: None,
+						emissive_texture: None,
+						casts_shadow: true,
+						visible_to_camera: true,
+						max_bounces: None,
+					},
+					Sphere {
+						radius: 0.5,
+						position: Vec3::new(-0.5, 0.0, 0.0),
+						color: Vec4::new(0.1, 1.0, 0.1, 1.0),
+						emission: Vec3::ZERO,
+						metallic: 0.0,
+						texture: None,
+						emissive_texture: None,
+						casts_shadow: true,
+						visible_to_camera: true,
+						max_bounces: None,
+					},
+					Sphere {
+						radius: 0.25,
+						position: Vec3::new(0.0, 0.0, -0.2),
+						color: Vec4::new(0.1, 0.1, 1.0, 1.0),
+						emission: Vec3::ZERO,
+						metallic: 0.0,
+						texture: None,
+						emissive_texture: None,
+						casts_shadow: true,
+						visible_to_camera: true,
+						max_bounces: None,
+					},
+					Sphere {
+						radius: 0.5,
+						position: Vec3::new(0.5, 0.0, 0.0),
+						color: Vec4::new(0.0, 1.0, 0.1, 1.0),
+						emission: Vec3::ZERO,
+						metallic: 0.0,
+						texture: None,
+						emissive_texture: None,
+						casts_shadow: true,
+						visible_to_camera: true,
+						max_bounces: None,
+					},
+					Sphere {
+						radius: 1.0,
+						position: Vec3::new(1.5, 0.0, 0.3),
+						color: Vec4::new(1.0, 0.1, 0.1, 1.0),
+						emission: Vec3::ZERO,
+						metallic: 0.0,
+						texture: None,
+						emissive_texture: None,
+						casts_shadow: true,
+						visible_to_camera: true,
+						max_bounces: None,
+					},
+				],
+				groups: Vec::new(),
+			}],
+			obbs: Vec::new(),
+			lights: Vec::new(),
+			points: Vec::new(),
+		}
+	}
+}
+
+/// Scene files that can be switched between, each one is parsed the first time it is used
+#[derive(Clone, Debug, Default)]
+pub struct Scenes {
+	entries: Vec<(PathBuf, Option<Scene>)>,
+}
+
+impl Scenes {
+	pub fn new(paths: impl IntoIterator<Item = impl Into<PathBuf>>) -> Self {
+		Self {
+			entries: paths.into_iter().map(|path| (path.into(), None)).collect(),
+		}
+	}
+
+	pub fn len(&self) -> usize {
+		self.entries.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.entries.is_empty()
+	}
+
+	pub fn path(&self, index: usize) -> Option<&Path> {
+		self.entries.get(index).map(|(path, _)| path.as_path())
+	}
+
+	/// Scene at `index`, parsing its file if this is the first time it is requested
+	pub fn get(&mut self, index: usize) -> anyhow::Result<&Scene> {
+		let count = self.len();
+		let Some((path, cached)) = self.entries.get_mut(index) else {
+			anyhow::bail!(
+				"There is no scene {}, only {count} scenes were given",
+				index + 1
+			);
+		};
+		let scene = match cached.take() {
+			Some(scene) => scene,
+			None => load_scene(path)?,
+		};
+		Ok(cached.insert(scene))
+	}
+}
+
+/// Snapshots kept by [`SceneHistory::default_capacity`]
+const DEFAULT_HISTORY_CAPACITY: usize = 64;
+
+/// Undo and redo stacks of committed scene edits.
+///
+/// Scenes are stored as RON like the scene files, so a snapshot costs about as much memory as saving the scene.
+#[derive(Clone, Debug)]
+pub struct SceneHistory {
+	/// Earlier snapshots, the oldest one is dropped once there are `capacity` of them
+	undo: VecDeque<String>,
+	redo: Vec<String>,
+	/// Snapshot of the last committed, undone or redone scene
+	current: String,
+	capacity: usize,
+}
+
+impl SceneHistory {
+	/// Starts an empty history at `scene` that keeps up to `capacity` undo steps
+	pub fn new(scene: &Scene, capacity: usize) -> anyhow::Result<Self> {
+		Ok(Self {
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			current: ron::to_string(scene)?,
+			capacity,
+		})
+	}
+
+	pub fn default_capacity(scene: &Scene) -> anyhow::Result<Self> {
+		Self::new(scene, DEFAULT_HISTORY_CAPACITY)
+	}
+
+	/// Records `scene` as an edit that can be undone, does nothing if it didn't change since the last snapshot.
+	/// Discards everything that could be redone
+	pub fn commit(&mut self, scene: &Scene) -> anyhow::Result<()> {
+		let snapshot = ron::to_string(scene)?;
+		if snapshot == self.current {
+			return Ok(());
+		}
+		self.undo
+			.push_back(std::mem::replace(&mut self.current, snapshot));
+		if self.undo.len() > self.capacity {
+			self.undo.pop_front();
+		}
+		self.redo.clear();
+		Ok(())
+	}
+
+	/// Scene before the last committed edit, `None` if there is nothing to undo
+	pub fn undo(&mut self) -> anyhow::Result<Option<Scene>> {
+		let Some(snapshot) = self.undo.pop_back() else {
+			return Ok(None);
+		};
+		self.redo
+			.push(std::mem::replace(&mut self.current, snapshot));
+		Ok(Some(ron::from_str(&self.current)?))
+	}
+
+	/// Scene of the last undone edit, `None` if there is nothing to redo
+	pub fn redo(&mut self) -> anyhow::Result<Option<Scene>> {
+		let Some(snapshot) = self.redo.pop() else {
+			return Ok(None);
+		};
+		self.undo
+			.push_back(std::mem::replace(&mut self.current, snapshot));
+		Ok(Some(ron::from_str(&self.current)?))
+	}
+}
+
+/// Path that [`load_scene`] reads from stdin instead of a file
+pub const STDIN_PATH: &str = "-";
+
+/// Parses the scene file at `path`, or everything piped into stdin if `path` is [`STDIN_PATH`]
+pub fn load_scene(path: impl AsRef<Path>) -> anyhow::Result<Scene> {
+	let path = path.as_ref();
+	let (source, origin) = if path == Path::new(STDIN_PATH) {
+		let mut source = String::new();
+		std::io::stdin()
+			.read_to_string(&mut source)
+			.context("Failed to read the scene from stdin")?;
+		(source, "stdin".to_owned())
+	} else {
+		let source = std::fs::read_to_string(path)
+			.with_context(|| format!("Failed to read the scene {}", path.display()))?;
+		(source, path.display().to_string())
+	};
+	ron::from_str(&source).with_context(|| format!("Invalid scene in {origin}"))
+}
+
+pub fn save_scene(scene: &Scene, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	save_ron(scene, path)
+}
+
+pub fn save_camera(camera: &Camera, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	save_ron(camera, path)
+}
+
+/// Fields of a [`Scene`] that describe how it is viewed
+#[derive(Serialize)]
+struct View<'a> {
+	camera: &'a Camera,
+	settings: &'a RenderSettings,
+}
+
+/// Fields of a [`Scene`] that change the rendered image, serialized by [`Scene::scene_hash`]
+#[derive(Serialize)]
+struct RenderState<'a> {
+	camera: &'a Camera,
+	settings: &'a RenderSettings,
+	spheres: Vec<Sphere>,
+	obbs: &'a [Obb],
+	lights: &'a [Light],
+}
+
+/// 64 bit FNV-1a, unlike the std hashers its output is specified and never changes
+struct Fnv1a(u64);
+
+impl Default for Fnv1a {
+	fn default() -> Self {
+		Self(0xcbf2_9ce4_8422_2325)
+	}
+}
+
+impl Write for Fnv1a {
+	fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+		for &byte in bytes {
+			self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0100_0000_01b3);
+		}
+		Ok(bytes.len())
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		Ok(())
+	}
+}
+
+/// `camera` and `settings` as a RON scene without spheres, which loads as an empty scene
+/// and whose fields can be pasted into a scene file
+pub fn view_to_ron(camera: &Camera, settings: &RenderSettings) -> anyhow::Result<String> {
+	let view = View { camera, settings };
+	Ok(ron::ser::to_string_pretty(
+		&view,
+		ron::ser::PrettyConfig::default(),
+	)?)
+}
+
+fn save_ron(value: &impl Serialize, path: impl AsRef<Path>) -> anyhow::Result<()> {
+	let source = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())?;
+	std::fs::write(path, source)?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::renderer::LightKind;
+
+	#[test]
+	fn example_group_places_the_foreground_spheres() {
+		let positions: Vec<_> = Scene::example()
+			.world_spheres()
+			.iter()
+			.map(|sphere| sphere.position)
+			.collect();
+		for expected in [
+			Vec3::new(-1.5, 0.0, 0.5),
+			Vec3::new(-0.5, 0.0, 0.2),
+			Vec3::ZERO,
+			Vec3::new(0.5, 0.0, 0.2),
+			Vec3::new(1.5, 0.0, 0.5),
+		] {
+			assert!(
+				positions
+					.iter()
+					.any(|position| position.abs_diff_eq(expected, 1e-6)),
+				"No sphere at {expected}"
+			);
+		}
+	}
+
+	#[test]
+	fn rotating_a_group_orbits_its_spheres() {
+		let mut scene = Scene::example();
+		let group = &mut scene.groups[0];
+		group.rotation = Quat::from_rotation_y(std::f32::consts::PI);
+		let (origin, first) = (group.translation, group.spheres[0].position);
+		let rotated = scene.world_spheres()[1].position;
+		assert!(rotated.abs_diff_eq(origin - first, 1e-5), "{rotated}");
+	}
+
+	#[test]
+	fn view_loads_as_a_scene() {
+		let mut camera = Camera::default();
+		camera.orbit(0.5, 0.25);
+		let settings = RenderSettings {
+			max_bounces: 7,
+			..RenderSettings::default()
+		};
+		let scene: Scene = ron::from_str(&view_to_ron(&camera, &settings).unwrap()).unwrap();
+		assert_eq!(scene.camera, camera);
+		assert_eq!(scene.settings, settings);
+		assert!(scene.spheres.is_empty());
+	}
+
+	#[test]
+	fn added_lights_and_boxes_are_saved() {
+		let light = Light {
+			kind: LightKind::Point {
+				position: Vec3::new(0.0, 2.0, 0.0),
+			},
+			color: Vec3::ONE,
+		};
+		let obb = Obb {
+			center: Vec3::ZERO,
+			half_extents: Vec3::splat(0.5),
+			rotation: Quat::IDENTITY,
+			color: Vec4::ONE,
+			emission: Vec3::ZERO,
+			metallic: 0.0,
+			casts_shadow: true,
+			visible_to_camera: true,
+			max_bounces: None,
+		};
+		let mut scene = Scene::new();
+		scene.add_obb(obb).add_light(light);
+		let loaded: Scene = ron::from_str(&ron::to_string(&scene).unwrap()).unwrap();
+		assert_eq!(loaded.obbs[0].half_extents, obb.half_extents);
+		assert_eq!(loaded.lights, [light]);
+	}
+}
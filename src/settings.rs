@@ -0,0 +1,271 @@
+use glam::Vec3;
+use serde::{Deserialize, Serialize};
+
+use crate::stats::FrameStats;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderSettings {
+	pub samples_per_pixel: u32,
+	/// Frame time in milliseconds that `samples_per_pixel` gets adapted to,
+	/// `None` keeps the sample count fixed
+	pub target_frame_ms: Option<f32>,
+	pub min_samples: u32,
+	pub max_samples: u32,
+	/// Maximum number of times a ray bounces off surfaces, `0` only renders direct light.
+	/// Materials can lower it with [`Sphere::max_bounces`](crate::renderer::Sphere::max_bounces)
+	pub max_bounces: u32,
+	/// Emitters sampled for direct light at every diffuse surface a path hits, at least `1`
+	pub shadow_samples: u32,
+	/// Paths traced from each camera ray, at least `1`.
+	/// Unlike `samples_per_pixel` they share the camera ray, so they only reduce the noise of the lighting
+	pub gi_samples: u32,
+	/// Most ray object intersection tests per pixel and frame, `0` for no limit.
+	/// Pixels that use up the budget stop tracing and keep the light found so far, so they come out too dark or
+	/// miss objects, but heavy scenes can't stall the gpu long enough for the driver to reset it
+	pub max_intersection_tests: u32,
+	/// Average samples over consecutive frames while nothing changes
+	pub accumulate: bool,
+	/// Fraction of the output resolution the scene is traced at, the result is upscaled by the post pass.
+	/// Values below `1` trade sharpness for frame rate
+	pub render_scale: f32,
+	/// Smooth sphere edges by how much of a pixel they cover,
+	/// only used while a single sample per pixel is taken and nothing has been accumulated yet
+	pub analytic_antialiasing: bool,
+	/// Width of the edges smoothed by `analytic_antialiasing` in pixels. Larger values look softer,
+	/// high-DPI displays may want less than `1`, and `0` gives hard edges
+	pub antialiasing_width: f32,
+	/// Mixed into the random numbers of every pixel together with the frame index.
+	/// Different seeds give different noise of the same quality, the same seed renders the same image again
+	pub seed: u32,
+	/// Let bright parts of the image glow
+	pub bloom: bool,
+	/// Brightness above which pixels start to glow
+	pub bloom_threshold: f32,
+	/// Strength of the glow added to the image
+	pub bloom_intensity: f32,
+	pub sky: Sky,
+	/// Brightness of the sky, both when seen directly and in reflections,
+	/// `0` gives a black background while the sun and emissive spheres still light the scene
+	pub env_intensity: f32,
+	pub crosshair: Crosshair,
+	pub toon: Toon,
+	/// Draw a dot for every point light, a cone for every spot light and an arrow in front of the camera
+	/// for every directional light over the image, in the color of the light
+	pub light_gizmos: bool,
+	/// What is shown instead of the lit image, for debugging
+	pub debug_view: DebugView,
+}
+
+/// Alternative outputs of the path tracer
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DebugView {
+	/// The path traced image
+	#[default]
+	Lit,
+	/// Color of the nearest surface including its texture, without any lighting, and the sky for misses
+	Albedo,
+	/// World space normal of the nearest surface from -1 to 1, zero for misses.
+	/// Negative components show up black, the values are meant to be saved with [`Renderer::capture_hdr`](crate::renderer::Renderer::capture_hdr)
+	Normal,
+}
+
+/// Cross drawn over the center of the image, to aim with the camera
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Crosshair {
+	pub enabled: bool,
+	/// Length of each arm from the center in physical pixels
+	pub size: f32,
+	/// Linear color, drawn over a black shadow
+	pub color: Vec3,
+}
+
+impl Default for Crosshair {
+	fn default() -> Self {
+		Self {
+			enabled: false,
+			size: 8.0,
+			color: Vec3::ONE,
+		}
+	}
+}
+
+/// Comic style post processing, drawn over the accumulated image like bloom
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Toon {
+	/// Draw lines where the depth or the normal of neighboring pixels changes abruptly,
+	/// around silhouettes and along the edges of boxes
+	pub outlines: bool,
+	/// Pixels covered by the outline on each side of an edge
+	pub outline_thickness: f32,
+	/// Linear color
+	pub outline_color: Vec3,
+	/// Number of brightness levels the image is reduced to for flat, cel shaded lighting, `0` keeps it smooth.
+	/// Works on the final brightness, so dark materials fall into lower bands than bright ones
+	pub bands: u32,
+}
+
+impl Default for Toon {
+	fn default() -> Self {
+		Self {
+			outlines: false,
+			outline_thickness: 1.0,
+			outline_color: Vec3::ZERO,
+			bands: 0,
+		}
+	}
+}
+
+/// Background seen by rays that miss every object, a vertical gradient
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Sky {
+	/// Color straight up
+	pub zenith: Vec3,
+	/// Color at and below the horizon
+	pub horizon: Vec3,
+}
+
+impl Default for Sky {
+	fn default() -> Self {
+		Self {
+			zenith: Vec3::new(0.3, 0.5, 0.9),
+			horizon: Vec3::new(0.8, 0.85, 0.9),
+		}
+	}
+}
+
+impl Default for RenderSettings {
+	fn default() -> Self {
+		Self {
+			samples_per_pixel: 1,
+			target_frame_ms: None,
+			min_samples: 1,
+			max_samples: 64,
+			max_bounces: 4,
+			shadow_samples: 1,
+			gi_samples: 1,
+			max_intersection_tests: 0,
+			accumulate: true,
+			render_scale: 1.0,
+			analytic_antialiasing: true,
+			antialiasing_width: 1.0,
+			seed: 0,
+			bloom: true,
+			bloom_threshold: 1.0,
+			bloom_intensity: 0.05,
+			sky: Sky::default(),
+			env_intensity: 1.0,
+			crosshair: Crosshair::default(),
+			toon: Toon::default(),
+			light_gizmos: false,
+			debug_view: DebugView::default(),
+		}
+	}
+}
+
+/// Named bundles of quality related [`RenderSettings`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Preset {
+	/// 1 sample per pixel and a single bounce, for moving around quickly
+	Preview,
+	/// 1 sample per pixel and 4 bounces, the defaults
+	#[default]
+	Balanced,
+	/// 4 samples per pixel and 8 bounces, for final images
+	HighQuality,
+}
+
+impl DebugView {
+	/// View after this one, wrapping around
+	pub fn next(self) -> Self {
+		match self {
+			Self::Lit => Self::Albedo,
+			Self::Albedo => Self::Normal,
+			Self::Normal => Self::Lit,
+		}
+	}
+}
+
+impl Preset {
+	/// Preset after this one, wrapping around
+	pub fn next(self) -> Self {
+		match self {
+			Self::Preview => Self::Balanced,
+			Self::Balanced => Self::HighQuality,
+			Self::HighQuality => Self::Preview,
+		}
+	}
+}
+
+impl RenderSettings {
+	/// Default settings with the values of `preset` applied
+	pub fn preset(preset: Preset) -> Self {
+		let mut settings = Self::default();
+		settings.apply_preset(preset);
+		settings
+	}
+
+	/// Sets `samples_per_pixel`, `max_bounces`, `shadow_samples`, `gi_samples`, `accumulate` and
+	/// `analytic_antialiasing`, everything else like the sky is kept
+	pub fn apply_preset(&mut self, preset: Preset) {
+		let (samples_per_pixel, max_bounces, shadow_samples, gi_samples) = match preset {
+			Preset::Preview => (1, 1, 1, 1),
+			Preset::Balanced => (1, 4, 1, 1),
+			Preset::HighQuality => (4, 8, 4, 2),
+		};
+		self.samples_per_pixel = samples_per_pixel;
+		self.max_bounces = max_bounces;
+		self.shadow_samples = shadow_samples;
+		self.gi_samples = gi_samples;
+		self.accumulate = true;
+		self.analytic_antialiasing = true;
+	}
+}
+
+/// Raises or lowers [`RenderSettings::samples_per_pixel`] to stay near [`RenderSettings::target_frame_ms`]
+#[derive(Debug, Default)]
+pub struct SampleController {
+	frames_until_update: u32,
+}
+
+impl SampleController {
+	/// Frames to wait after a change, so the averaged frame time can settle before the next one
+	const COOLDOWN_FRAMES: u32 = 10;
+	/// Relative deviation from the target frame time that is tolerated without changing anything
+	const TOLERANCE: f32 = 0.15;
+
+	/// Returns `true` if `settings` changed and need to be uploaded again
+	pub fn update(&mut self, settings: &mut RenderSettings, stats: &FrameStats) -> bool {
+		let Some(target_ms) = settings.target_frame_ms else {
+			return false;
+		};
+		if self.frames_until_update > 0 {
+			self.frames_until_update -= 1;
+			return false;
+		}
+
+		let min = settings.min_samples.max(1);
+		let max = settings.max_samples.max(min);
+		let frame_ms = stats.average_ms();
+
+		// Step by at most one sample at a time to avoid oscillating around the target
+		let samples = if frame_ms > target_ms * (1.0 + Self::TOLERANCE) {
+			settings.samples_per_pixel.saturating_sub(1)
+		} else if frame_ms < target_ms * (1.0 - Self::TOLERANCE) {
+			settings.samples_per_pixel + 1
+		} else {
+			settings.samples_per_pixel
+		}
+		.clamp(min, max);
+
+		if samples == settings.samples_per_pixel {
+			return false;
+		}
+		settings.samples_per_pixel = samples;
+		self.frames_until_update = Self::COOLDOWN_FRAMES;
+		true
+	}
+}
@@ -22,66 +22,726 @@ fn vs_main(
 }
 
 struct Camera {
+  position: vec3<f32>,
   width: u32,
+  forward: vec3<f32>,
   height: u32,
+  up: vec3<f32>,
+  // vertical field of view in radians
+  fov: f32,
+  // hits closer than near or further away than far are ignored
+  near: f32,
+  far: f32,
+  // center (xy) and size (zw) of the traced image within the whole image in uv coordinates,
+  // (0, 0, 1, 1) unless the image is rendered in tiles
+  tile: vec4<f32>,
 }
 @group(0)
 @binding(0)
 var<uniform> camera: Camera;
+// the camera the history texture was rendered with, see sample_history
+@group(0)
+@binding(1)
+var<uniform> previous_camera: Camera;
 
 struct Sphere {
   position: vec3<f32>,
   radius: f32,
   color: vec4<f32>,
+  emission: vec3<f32>,
+  // probability of a mirror reflection instead of a diffuse bounce
+  metallic: f32,
+  // see casts_shadow_flag, visible_to_camera_flag, bounces_shift, texture_shift and emissive_texture_shift
+  flags: u32,
+}
+// The sphere buffer (group 1, binding 0) is declared in spheres.wgsl or spheres_packed.wgsl,
+// depending on the storage format, which also define load_sphere()
+
+struct Emitters {
+  count: u32,
+  // indices of all spheres with a non zero emission
+  indices: array<u32>,
+}
+@group(1)
+@binding(1)
+var<storage, read> emitters: Emitters;
+
+// Box rotated around its center, see hit_obb
+struct Obb {
+  center: vec3<f32>,
+  metallic: f32,
+  // half of the size along each axis of the box
+  half_extents: vec3<f32>,
+  // see casts_shadow_flag, visible_to_camera_flag and bounces_shift
+  flags: u32,
+  // quaternion from box to world space
+  rotation: vec4<f32>,
+  color: vec4<f32>,
+  emission: vec3<f32>,
+}
+// holds a placeholder when the scene has no boxes, use settings.obb_count
+@group(1)
+@binding(4)
+var<storage, read> obbs: array<Obb>;
+
+// Light without a surface, see light_directional, light_point and light_spot
+struct Light {
+  position: vec3<f32>,
+  kind: u32,
+  // towards a directional light or along the axis of a spot light, normalized
+  direction: vec3<f32>,
+  // cosine of the angle between the axis and the edge of a spot light
+  cos_angle: f32,
+  // radiance of a white surface facing the light, at a distance of 1 for point and spot lights
+  color: vec3<f32>,
 }
+// holds a placeholder when the scene has no lights, use settings.light_count
 @group(1)
+@binding(5)
+var<storage, read> lights: array<Light>;
+
+// one layer per texture added to the renderer
+@group(1)
+@binding(2)
+var textures: texture_2d_array<f32>;
+@group(1)
+@binding(3)
+var texture_sampler: sampler;
+
+struct Settings {
+  sky_zenith: vec3<f32>,
+  samples_per_pixel: u32,
+  sky_horizon: vec3<f32>,
+  max_bounces: u32,
+  // number of frames accumulated before this one
+  frame: u32,
+  // smooth sphere edges by their coverage when only a single unjittered sample is taken
+  analytic_antialiasing: u32,
+  // brightness multiplier of the sky
+  env_intensity: f32,
+  // number of layers in textures that hold an image
+  texture_count: u32,
+  // the sphere buffer holds a placeholder when the scene is empty, so its length can't be used
+  sphere_count: u32,
+  // emitters sampled at every diffuse hit, at least 1
+  shadow_samples: u32,
+  // paths traced from every camera ray, at least 1
+  gi_samples: u32,
+  // see debug_view_lit, debug_view_albedo and debug_view_normal
+  debug_view: u32,
+  obb_count: u32,
+  light_count: u32,
+  // intersection tests per pixel before tracing stops, 0 for no limit
+  max_intersection_tests: u32,
+  // 1 if history holds the previous frame seen by previous_camera
+  history_valid: u32,
+  // width of the edges of trace_antialiased in pixels, 0 for hard edges
+  antialiasing_width: f32,
+  // chosen by the user, picks the noise pattern together with frame
+  seed: u32,
+}
+@group(2)
 @binding(0)
-var<storage, read> spheres: array<Sphere>;
+var<uniform> settings: Settings;
+
+// max_spheres is appended by the renderer from MAX_SPHERES
+fn sphere_count() -> u32 {
+    return min(settings.sphere_count, max_spheres);
+}
+
+// sum of all samples (rgb) and the sample count (a) for every pixel
+@group(3)
+@binding(0)
+var<storage, read_write> accumulation: array<vec4<f32>>;
+// radiance of the previous frame, only kept if the renderer was created with a temporal history
+@group(3)
+@binding(1)
+var history: texture_2d<f32>;
+@group(3)
+@binding(2)
+var history_sampler: sampler;
+
+// Direction towards the sun, its irradiance is pi so a lit white surface has a radiance of 1
+const sun_direction = vec3<f32>(0.57735027, 0.57735027, -0.57735027);
+// Offset of secondary rays from the surface they start on, prevents self intersection
+const ray_epsilon = 0.001;
+// Bounces that always happen before russian roulette may terminate a path
+const min_bounces = 2u;
+const pi = 3.14159265;
+// Set in Sphere.flags for spheres that block shadow rays
+const casts_shadow_flag = 1u;
+// Set in Sphere.flags for spheres that are hit by camera rays
+const visible_to_camera_flag = 2u;
+// Passed to intersect_shadow when the light is not a sphere
+const no_light = 0xffffffffu;
+// Values of Light.kind
+const light_directional = 0u;
+const light_point = 1u;
+const light_spot = 2u;
+// Values of settings.debug_view
+const debug_view_lit = 0u;
+const debug_view_albedo = 1u;
+const debug_view_normal = 2u;
+// The 8 bits of Sphere.flags and Obb.flags from here on store the bounce limit of the material plus one,
+// 0 to only use settings.max_bounces
+const bounces_shift = 8u;
+// The 8 bits of Sphere.flags from here on store the texture index plus one, 0 for untextured spheres
+const texture_shift = 16u;
+// The bits of Sphere.flags from here on store the emissive texture index plus one, 0 for none
+const emissive_texture_shift = 24u;
+
+struct GeometryOutput {
+  // average of all samples accumulated for the pixel
+  @location(0) radiance: vec4<f32>,
+  // distance along the center ray of the pixel to the nearest visible surface, camera.far for the sky
+  @location(1) depth: f32,
+  // world space normal of that surface, zero for the sky
+  @location(2) normal: vec4<f32>,
+}
 
 @fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let aspect_ratio = f32(camera.width) / f32(camera.height);
-    let height = 1.0;
-    let width = 1.0 * aspect_ratio;
+fn fs_main(in: VertexOutput) -> GeometryOutput {
+    let pixel_index = u32(in.position.x) + u32(in.position.y) * camera.width;
+    var seed = pcg_hash(pixel_index ^ pcg_hash(settings.frame ^ pcg_hash(settings.seed)));
+    // size of one pixel in uv space
+    let pixel_size = 2.0 / vec2(f32(camera.width), f32(camera.height));
+
+    // accumulated frames need different sample positions to antialias
+    let jittered = settings.samples_per_pixel > 1u || settings.frame > 0u;
+
+    var color = vec3(0.0);
+    // fewer than settings.samples_per_pixel if the intersection budget ran out
+    var samples = 0u;
+    if !jittered && settings.analytic_antialiasing != 0u {
+        color = trace_antialiased(camera_ray(in.uv), pixel_angle() * settings.antialiasing_width, &seed);
+        samples = 1u;
+    } else {
+        for (; samples < settings.samples_per_pixel && !budget_exhausted; samples += 1u) {
+            var jitter = vec2(0.0);
+            if jittered {
+                jitter = (vec2(random(&seed), random(&seed)) - 0.5) * pixel_size;
+            }
+            color += trace_paths(camera_ray(in.uv + jitter), &seed);
+        }
+    }
+
+    var accumulated = vec4(color, f32(samples));
+    if settings.frame > 0u {
+        accumulated += accumulation[pixel_index];
+    }
+    accumulation[pixel_index] = accumulated;
+
+    var out: GeometryOutput;
+    out.radiance = vec4(accumulated.rgb / max(accumulated.a, 1.0), 1.0);
+    // the depth and normal don't count towards the budget, it only takes a single ray
+    intersection_tests = 0u;
+    budget_exhausted = false;
+    let ray = camera_ray(in.uv);
+    let hit = intersect_camera(ray, camera.near, camera.far);
+    out.depth = select(camera.far, hit.distance, hit.intersected);
+    if hit.intersected {
+        out.normal = vec4(hit_surface(ray, hit, hit.distance * pixel_angle()).normal, 0.0);
+    }
+    return out;
+}
 
-    let coord = vec2(in.uv.x * width, height * in.uv.y) * 2.0;
+// Radiance the previous frame showed where position was seen, alpha is 0 if it was outside of that frame
+// or there is no history. Whether position was actually visible or hidden behind something is up to the caller
+fn sample_history(position: vec3<f32>) -> vec4<f32> {
+    if settings.history_valid == 0u {
+        return vec4(0.0);
+    }
+    // inverse of camera_ray for the previous camera
+    let offset = position - previous_camera.position;
+    let depth = dot(offset, previous_camera.forward);
+    if depth < previous_camera.near {
+        return vec4(0.0);
+    }
+    let tile = previous_camera.tile;
+    let aspect_ratio = f32(previous_camera.width) / f32(previous_camera.height) * tile.w / tile.z;
+    let height = tan(previous_camera.fov / 2.0);
+    let width = height * aspect_ratio;
+    let right = cross(previous_camera.up, previous_camera.forward);
+    let uv = (vec2(dot(offset, right) / (depth * width), dot(offset, previous_camera.up) / (depth * height)) - tile.xy) / tile.zw;
+    if any(abs(uv) > vec2(1.0)) {
+        return vec4(0.0);
+    }
+    // texture coordinates point down
+    return textureSampleLevel(history, history_sampler, vec2(uv.x, -uv.y) * 0.5 + 0.5, 0.0);
+}
+
+fn camera_ray(target_uv: vec2<f32>) -> Ray {
+    let uv = camera.tile.xy + target_uv * camera.tile.zw;
+    let aspect_ratio = f32(camera.width) / f32(camera.height) * camera.tile.w / camera.tile.z;
+    let height = tan(camera.fov / 2.0);
+    let width = height * aspect_ratio;
+
+    let coord = vec2(uv.x * width, height * uv.y);
+    let right = cross(camera.up, camera.forward);
 
     var ray: Ray;
-    ray.origin = vec3(0.0, 0.0, -2.0);
-    ray.direction = normalize(vec3(coord.x, coord.y, 0.0) - ray.origin);
-    // ray.origin = vec3(coord, -3.0);
-    // ray.direction = vec3(0.0, 0.0, 1.0);
+    ray.origin = camera.position;
+    ray.direction = normalize(camera.forward + right * coord.x + camera.up * coord.y);
+    return ray;
+}
+
+// Traces both sides of the nearest sphere silhouette that passes through the pixel footprint
+// and blends them by the fraction of the footprint covered by the sphere, pixel_angle sets the width of the footprint
+fn trace_antialiased(ray: Ray, pixel_angle: f32, seed: ptr<function, u32>) -> vec3<f32> {
+    let hit = intersect_camera(ray, camera.near, camera.far);
+
+    var edge_found = false;
+    var edge_t = camera.far;
+    // point on the ray closest to the center of the sphere
+    var edge_point = vec3(0.0);
+    var to_center = vec3(0.0);
+    // distance of the ray from the silhouette, negative inside
+    var edge_distance = 0.0;
+    // width of the pixel footprint at the silhouette
+    var footprint = 0.0;
+
+    for (var i = 0u; i < sphere_count() && count_intersection_test(); i += 1u) {
+        let sphere = load_sphere(i);
+        if (sphere.flags & visible_to_camera_flag) == 0u {
+            continue;
+        }
+        let t = dot(sphere.position - ray.origin, ray.direction);
+        let closest = position_on_ray(ray, t);
+        let distance = length(sphere.position - closest);
+        let width = t * pixel_angle;
+        let hidden = hit.intersected && (hit.obb || hit.index != i) && hit.distance < t - sphere.radius;
+        if t <= camera.near || t >= edge_t || hidden || distance == 0.0
+            || abs(distance - sphere.radius) >= width * 0.5 {
+            continue;
+        }
+        edge_found = true;
+        edge_t = t;
+        edge_point = closest;
+        to_center = (sphere.position - closest) / distance;
+        edge_distance = distance - sphere.radius;
+        footprint = width;
+    }
+
+    if !edge_found {
+        return trace_paths(ray, seed);
+    }
+
+    // rays through the middle of the covered and the uncovered part of the footprint
+    var inside = ray;
+    inside.direction = normalize(edge_point + to_center * (edge_distance + footprint * 0.25) - ray.origin);
+    var outside = ray;
+    outside.direction = normalize(edge_point + to_center * (edge_distance - footprint * 0.25) - ray.origin);
+
+    let coverage = clamp(0.5 - edge_distance / footprint, 0.0, 1.0);
+    return mix(trace_paths(outside, seed), trace_paths(inside, seed), coverage);
+}
+
+// Intersection tests of the current pixel so far, see settings.max_intersection_tests
+var<private> intersection_tests: u32 = 0u;
+// set once a test was skipped, hits found since are incomplete, so tracing ends with the light found so far
+var<private> budget_exhausted: bool = false;
+
+// Counts an intersection test, false if there are none left to stop the loop doing it
+fn count_intersection_test() -> bool {
+    if settings.max_intersection_tests != 0u && intersection_tests >= settings.max_intersection_tests {
+        budget_exhausted = true;
+        return false;
+    }
+    intersection_tests += 1u;
+    return true;
+}
+
+// Angle covered by one pixel, see camera_ray
+fn pixel_angle() -> f32 {
+    return 2.0 / f32(camera.height) * camera.tile.w * tan(camera.fov / 2.0);
+}
+
+// Average of settings.gi_samples paths that start with the same camera ray
+fn trace_paths(ray: Ray, seed: ptr<function, u32>) -> vec3<f32> {
+    if settings.debug_view == debug_view_albedo {
+        return albedo(ray);
+    }
+    if settings.debug_view == debug_view_normal {
+        return surface_normal(ray);
+    }
+    var radiance = vec3(0.0);
+    var paths = 0u;
+    for (; paths < settings.gi_samples && !budget_exhausted; paths += 1u) {
+        radiance += trace(ray, seed);
+    }
+    return radiance / f32(max(paths, 1u));
+}
+
+// Follows a path through the scene and returns the light arriving along it
+fn trace(primary_ray: Ray, seed: ptr<function, u32>) -> vec3<f32> {
+    var ray = primary_ray;
+    var radiance = vec3(0.0);
+    // fraction of light that reaches the camera from the current path segment
+    var throughput = vec3(1.0);
+    var t_min = camera.near;
+    // pdf of the direction of the current ray, 0 if it was not sampled from a diffuse surface
+    var bsdf_pdf = 0.0;
+    // length of the path so far, the pixel footprint widens with it like a cone
+    var path_length = 0.0;
+
+    for (var bounce = 0u; bounce <= settings.max_bounces; bounce += 1u) {
+        var hit: Hit;
+        if bounce == 0u {
+            hit = intersect_camera(ray, t_min, camera.far);
+        } else {
+            hit = intersect_scene(ray, t_min, camera.far);
+        }
+        if budget_exhausted {
+            break;
+        }
+        if !hit.intersected {
+            radiance += throughput * sky(ray.direction);
+            break;
+        }
 
+        path_length += hit.distance;
+        let surface = hit_surface(ray, hit, path_length * pixel_angle());
+        var normal = surface.normal;
+        // rays starting inside of an object hit its back side
+        if dot(normal, ray.direction) > 0.0 {
+            normal = -normal;
+        }
+
+        if bsdf_pdf > 0.0 && !hit.obb {
+            // this emitter could also have been sampled directly at the previous bounce
+            let light_pdf = emitter_pdf(load_sphere(hit.index), ray.origin);
+            radiance += throughput * surface.emission * power_heuristic(bsdf_pdf, light_pdf);
+        } else {
+            radiance += throughput * surface.emission;
+        }
+        throughput *= surface.color;
+
+        ray.origin = surface.position + normal * ray_epsilon;
+        t_min = ray_epsilon;
+        if random(seed) < surface.metallic {
+            ray.direction = reflect(ray.direction, normal);
+            bsdf_pdf = 0.0;
+        } else {
+            radiance += throughput * direct_light(ray.origin, normal);
+            var emitted = vec3(0.0);
+            for (var i = 0u; i < settings.shadow_samples; i += 1u) {
+                emitted += sample_emitter(ray.origin, normal, seed);
+            }
+            radiance += throughput * emitted / f32(settings.shadow_samples);
+            ray.direction = cosine_sample_hemisphere(normal, seed);
+            bsdf_pdf = max(dot(normal, ray.direction), 0.0) / pi;
+        }
+
+        // the material can end the path earlier than settings.max_bounces, but not later
+        if surface.max_bounces != 0u && bounce + 1u >= surface.max_bounces {
+            break;
+        }
+
+        // russian roulette, terminate paths that carry little light without biasing the result
+        if bounce >= min_bounces {
+            let survival = clamp(max(throughput.r, max(throughput.g, throughput.b)), 0.05, 1.0);
+            if random(seed) > survival {
+                break;
+            }
+            throughput /= survival;
+        }
+    }
+
+    return radiance;
+}
+
+// Unlit color of the surface seen by a camera ray
+fn albedo(ray: Ray) -> vec3<f32> {
+    let hit = intersect_camera(ray, camera.near, camera.far);
+    if !hit.intersected {
+        return sky(ray.direction);
+    }
+    return hit_surface(ray, hit, hit.distance * pixel_angle()).color;
+}
+
+// Normal of the first surface the camera sees, zero for misses
+fn surface_normal(ray: Ray) -> vec3<f32> {
+    let hit = intersect_camera(ray, camera.near, camera.far);
+    if !hit.intersected {
+        return vec3(0.0);
+    }
+    return hit_surface(ray, hit, hit.distance * pixel_angle()).normal;
+}
+
+// Material and orientation of a hit sphere or box
+struct Surface {
+  position: vec3<f32>,
+  // points away from the object, not necessarily towards the ray
+  normal: vec3<f32>,
+  color: vec3<f32>,
+  emission: vec3<f32>,
+  metallic: f32,
+  // bounce limit plus one, see bounces_shift
+  max_bounces: u32,
+}
+
+// footprint is the width of the pixel seen at the hit, orthogonal to the ray
+fn hit_surface(ray: Ray, hit: Hit, footprint: f32) -> Surface {
+    var surface: Surface;
+    surface.position = position_on_ray(ray, hit.distance);
+    if hit.obb {
+        let obb = obbs[hit.index];
+        surface.normal = obb_normal(obb, surface.position);
+        surface.color = obb.color.rgb;
+        surface.emission = obb.emission;
+        surface.metallic = obb.metallic;
+        surface.max_bounces = (obb.flags >> bounces_shift) & 0xffu;
+    } else {
+        let sphere = load_sphere(hit.index);
+        surface.normal = sphere_normal(sphere, surface.position);
+        surface.color = sphere.color.rgb
+            * sample_texture(sphere, texture_shift, surface.normal, ray.direction, footprint);
+        surface.emission = sphere.emission
+            * sample_texture(sphere, emissive_texture_shift, surface.normal, ray.direction, footprint);
+        surface.metallic = sphere.metallic;
+        surface.max_bounces = (sphere.flags >> bounces_shift) & 0xffu;
+    }
+    return surface;
+}
+
+// Next event estimation, light arriving at a diffuse surface from a randomly chosen emitter,
+// without the surface color
+fn sample_emitter(position: vec3<f32>, normal: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    if emitters.count == 0u {
+        return vec3(0.0);
+    }
+    let index = emitters.indices[min(u32(random(seed) * f32(emitters.count)), emitters.count - 1u)];
+    let emitter = load_sphere(index);
+
+    // uniformly sample the cone of directions in which the emitter is visible
+    let to_center = emitter.position - position;
+    let distance_squared = dot(to_center, to_center);
+    let radius_squared = emitter.radius * emitter.radius;
+    if distance_squared <= radius_squared {
+        return vec3(0.0);
+    }
+    let cos_max = sqrt(1.0 - radius_squared / distance_squared);
+    let cos_theta = 1.0 - random(seed) * (1.0 - cos_max);
+    let sin_theta = sqrt(max(1.0 - cos_theta * cos_theta, 0.0));
+    let phi = random(seed) * 2.0 * pi;
+    let direction = orthonormal_basis(normalize(to_center))
+        * vec3(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+
+    let cos_surface = dot(normal, direction);
+    if cos_surface <= 0.0 {
+        return vec3(0.0);
+    }
+
+    var shadow_ray: Ray;
+    shadow_ray.origin = position;
+    shadow_ray.direction = direction;
+    let hit = intersect_shadow(shadow_ray, ray_epsilon, camera.far, index);
+    if !hit.intersected || hit.obb || hit.index != index || budget_exhausted {
+        return vec3(0.0);
+    }
+
+    // emissive textures are looked up at the sampled point, at their sharpest level as there is no pixel footprint
+    let normal_on_emitter = sphere_normal(emitter, position_on_ray(shadow_ray, hit.distance));
+    let emission = emitter.emission
+        * sample_texture(emitter, emissive_texture_shift, normal_on_emitter, direction, 0.0);
+
+    let light_pdf = 1.0 / (f32(emitters.count) * 2.0 * pi * (1.0 - cos_max));
+    let bsdf_pdf = cos_surface / pi;
+    // the lambertian brdf (1 / pi) times the cosine term equals bsdf_pdf
+    return emission * bsdf_pdf * power_heuristic(light_pdf, bsdf_pdf) / light_pdf;
+}
+
+// Probability density of sample_emitter choosing a direction from position towards emitter
+fn emitter_pdf(emitter: Sphere, position: vec3<f32>) -> f32 {
+    let to_center = emitter.position - position;
+    let distance_squared = dot(to_center, to_center);
+    let radius_squared = emitter.radius * emitter.radius;
+    if emitters.count == 0u || distance_squared <= radius_squared {
+        return 0.0;
+    }
+    let cos_max = sqrt(1.0 - radius_squared / distance_squared);
+    return 1.0 / (f32(emitters.count) * 2.0 * pi * (1.0 - cos_max));
+}
+
+// Multiple importance sampling weight of a sample taken with pdf a, when it could also have been taken with pdf b
+fn power_heuristic(a: f32, b: f32) -> f32 {
+    return (a * a) / (a * a + b * b);
+}
+
+// Rotation from +z to n, see "Building an Orthonormal Basis, Revisited" (Duff et al.)
+fn orthonormal_basis(n: vec3<f32>) -> mat3x3<f32> {
+    let sign = select(-1.0, 1.0, n.z >= 0.0);
+    let a = -1.0 / (sign + n.z);
+    let b = n.x * n.y * a;
+    let tangent = vec3(1.0 + sign * n.x * n.x * a, sign * b, -sign * n.x);
+    let bitangent = vec3(b, sign + n.y * n.y * a, -n.y);
+    return mat3x3(tangent, bitangent, n);
+}
+
+// Vertical gradient from the horizon to the zenith
+fn sky(direction: vec3<f32>) -> vec3<f32> {
+    let color = mix(settings.sky_horizon, settings.sky_zenith, clamp(direction.y, 0.0, 1.0));
+    return color * settings.env_intensity;
+}
+
+// Light arriving from the sun and all lights at a diffuse surface, without the surface color
+fn direct_light(position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    var light = vec3(unshadowed(position, normal, sun_direction, camera.far));
+    for (var i = 0u; i < settings.light_count; i += 1u) {
+        light += light_contribution(lights[i], position, normal);
+    }
+    return light;
+}
+
+// Light arriving from a single light at a diffuse surface, without the surface color
+fn light_contribution(light: Light, position: vec3<f32>, normal: vec3<f32>) -> vec3<f32> {
+    switch light.kind {
+        case 0u /* light_directional */: {
+            return light.color * unshadowed(position, normal, light.direction, camera.far);
+        }
+        case 1u, 2u /* light_point, light_spot */: {
+            let offset = light.position - position;
+            let distance = length(offset);
+            let direction = offset / distance;
+            if light.kind == light_spot && dot(-direction, light.direction) < light.cos_angle {
+                return vec3(0.0);
+            }
+            return light.color * unshadowed(position, normal, direction, distance) / (distance * distance);
+        }
+        default: {
+            return vec3(0.0);
+        }
+    }
+}
+
+// Cosine between normal and the direction towards a light, 0 if something is in the way within distance
+fn unshadowed(position: vec3<f32>, normal: vec3<f32>, direction: vec3<f32>, distance: f32) -> f32 {
+    let cos_theta = dot(normal, direction);
+    if cos_theta <= 0.0 {
+        return 0.0;
+    }
+    var shadow_ray: Ray;
+    shadow_ray.origin = position;
+    shadow_ray.direction = direction;
+    // an incomplete shadow test counts as shadowed, so pixels out of budget don't leak light
+    if intersect_shadow(shadow_ray, ray_epsilon, distance, no_light).intersected || budget_exhausted {
+        return 0.0;
+    }
+    return cos_theta;
+}
+
+// Random direction around normal, directions close to it are more likely (pdf = cos(theta) / pi)
+fn cosine_sample_hemisphere(normal: vec3<f32>, seed: ptr<function, u32>) -> vec3<f32> {
+    // adding a uniformly distributed point on the unit sphere to the normal
+    // results in a cosine weighted distribution
+    let z = random(seed) * 2.0 - 1.0;
+    let angle = random(seed) * 6.2831853;
+    let r = sqrt(1.0 - z * z);
+    let direction = normal + vec3(r * cos(angle), r * sin(angle), z);
+    if dot(direction, direction) < 1e-8 {
+        return normal;
+    }
+    return normalize(direction);
+}
+
+// Nearest hit of any sphere or box with t_min <= distance <= t_max
+fn intersect_scene(ray: Ray, t_min: f32, t_max: f32) -> Hit {
     var hit: Hit;
-    hit.distance = f32_max;
-    var nearest_sphere: Sphere;
+    hit.distance = t_max;
 
-    for (var i = 0u; i <= arrayLength(&spheres); i += 1u) {
-        let sphere = spheres[i];
-        let maybe_hit = hit_sphere(ray, sphere);
-        if maybe_hit.intersected && hit.distance > maybe_hit.distance {
+    for (var i = 0u; i < sphere_count() && count_intersection_test(); i += 1u) {
+        let maybe_hit = hit_sphere(ray, load_sphere(i), t_min, hit.distance);
+        if maybe_hit.intersected {
             hit = maybe_hit;
-            nearest_sphere = sphere;
+            hit.index = i;
         }
     }
 
-    if !hit.intersected {
-        return vec4(0.0, 0.0, 0.0, 1.0);
+    return intersect_obbs(ray, t_min, hit, 0u);
+}
+
+// Nearest of closest and the hits of boxes that have all bits of required_flags set
+fn intersect_obbs(ray: Ray, t_min: f32, closest: Hit, required_flags: u32) -> Hit {
+    var hit = closest;
+    for (var i = 0u; i < settings.obb_count && count_intersection_test(); i += 1u) {
+        let obb = obbs[i];
+        if (obb.flags & required_flags) != required_flags {
+            continue;
+        }
+        let maybe_hit = hit_obb(ray, obb, t_min, hit.distance);
+        if maybe_hit.intersected {
+            hit = maybe_hit;
+            hit.index = i;
+            hit.obb = true;
+        }
+    }
+    return hit;
+}
+
+// Like intersect_scene, but ignores objects that aren't visible to the camera
+fn intersect_camera(ray: Ray, t_min: f32, t_max: f32) -> Hit {
+    var hit: Hit;
+    hit.distance = t_max;
+
+    for (var i = 0u; i < sphere_count() && count_intersection_test(); i += 1u) {
+        let sphere = load_sphere(i);
+        if (sphere.flags & visible_to_camera_flag) == 0u {
+            continue;
+        }
+        let maybe_hit = hit_sphere(ray, sphere, t_min, hit.distance);
+        if maybe_hit.intersected {
+            hit = maybe_hit;
+            hit.index = i;
+        }
     }
 
-    let hit_pos = position_on_ray(ray, hit.distance);
-    let normal = sphere_normal(nearest_sphere, hit_pos);
-    let light = dot(normal, normalize(vec3(1.0, 1.0, -1.0)));
-    let color = nearest_sphere.color * light;
-    return color;
+    return intersect_obbs(ray, t_min, hit, visible_to_camera_flag);
+}
+
+// Like intersect_scene, but ignores objects that don't cast shadows, except for the sphere at index light
+fn intersect_shadow(ray: Ray, t_min: f32, t_max: f32, light: u32) -> Hit {
+    var hit: Hit;
+    hit.distance = t_max;
+
+    for (var i = 0u; i < sphere_count() && count_intersection_test(); i += 1u) {
+        let sphere = load_sphere(i);
+        if (sphere.flags & casts_shadow_flag) == 0u && i != light {
+            continue;
+        }
+        let maybe_hit = hit_sphere(ray, sphere, t_min, hit.distance);
+        if maybe_hit.intersected {
+            hit = maybe_hit;
+            hit.index = i;
+        }
+    }
+
+    return intersect_obbs(ray, t_min, hit, casts_shadow_flag);
+}
+
+// PCG hash, see https://www.reedbeta.com/blog/hash-functions-for-gpu-rendering/
+fn pcg_hash(input: u32) -> u32 {
+    let state = input * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Random number in [0, 1], advances the seed
+fn random(seed: ptr<function, u32>) -> f32 {
+    *seed = pcg_hash(*seed);
+    return f32(*seed) / 4294967295.0;
 }
 
 struct Hit {
   intersected: bool,
   distance: f32,
+  // index of the hit sphere or box, only set by intersect_scene, intersect_camera and intersect_shadow
+  index: u32,
+  // whether index refers to obbs instead of the spheres
+  obb: bool,
 }
 
-fn hit_sphere(ray: Ray, sphere: Sphere) -> Hit {
+// Only hits with t_min <= distance <= t_max are reported
+fn hit_sphere(ray: Ray, sphere: Sphere, t_min: f32, t_max: f32) -> Hit {
     // a = ray.origin
     // b = ray.direction
     // r = sphere.radius
@@ -113,13 +773,17 @@ fn hit_sphere(ray: Ray, sphere: Sphere) -> Hit {
     // e = (bx^2 + by^2) = b*b
     // f = 2(ax*bx + ay*by) = 2*a*b
     // g = ax^2 + ay^2 - r^2 = a*a - r^2
+    // with h = f/2 = a*b the roots are t = (-h +/- sqrt(h^2 - e*g)) / e
     let e = dot(b, b);
-    let f = 2.0 * dot(a, b);
+    let h = dot(a, b);
     let g = dot(a, a) - (sphere.radius * sphere.radius);
 
     // discriminant
-    // d = f^2 - 4eg
-    let d = f * f - 4.0 * e * g;
+    // d = h^2 - e*g = e * (r^2 - |a - (h/e)*b|^2)
+    // the second form subtracts the squared distance between the ray and the center from r^2,
+    // instead of two large and nearly equal numbers when the sphere is big or far away
+    let closest = a - (h / e) * b;
+    let d = e * (sphere.radius * sphere.radius - dot(closest, closest));
 
     var hit: Hit;
 
@@ -128,20 +792,105 @@ fn hit_sphere(ray: Ray, sphere: Sphere) -> Hit {
         return hit;
     }
 
-    // quadratic formula
-    // t = (-f +/- sqrt(d)) / 2e
-    // let t_far = (-f + sqrt(d)) / (2.0 * e);
-    let t_near = (-f - sqrt(d)) / (2.0 * e);
+    // -h and the square root are added with the same sign, so they can't cancel each other out,
+    // the other root follows from t0 * t1 = g/e
+    let q = -(h + select(-1.0, 1.0, h >= 0.0) * sqrt(d));
+    let t0 = q / e;
+    let t1 = g / q;
+    var t = min(t0, t1);
+    // the near intersection is behind the ray origin, use the far one
+    // (happens when the ray starts inside of the sphere)
+    if t < t_min {
+        t = max(t0, t1);
+    }
 
-    hit.intersected = true;
-    hit.distance = t_near;
+    hit.intersected = t >= t_min && t <= t_max;
+    hit.distance = t;
     return hit;
 }
 
+// Slab test in the space of the box, only hits with t_min <= distance <= t_max are reported
+fn hit_obb(ray: Ray, obb: Obb, t_min: f32, t_max: f32) -> Hit {
+    let inverse = vec4(-obb.rotation.xyz, obb.rotation.w);
+    let origin = rotate(inverse, ray.origin - obb.center);
+    var direction = rotate(inverse, ray.direction);
+    // rays parallel to a pair of sides get a huge but finite distance to them instead of dividing by 0
+    direction = select(direction, vec3(1e-12), abs(direction) < vec3(1e-12));
+
+    let t0 = (-obb.half_extents - origin) / direction;
+    let t1 = (obb.half_extents - origin) / direction;
+    let entry = min(t0, t1);
+    let exit = max(t0, t1);
+    let t_entry = max(entry.x, max(entry.y, entry.z));
+    let t_exit = min(exit.x, min(exit.y, exit.z));
+
+    var hit: Hit;
+    // rays starting inside of the box hit it where they leave
+    let t = select(t_entry, t_exit, t_entry < t_min);
+    hit.intersected = t_entry <= t_exit && t >= t_min && t <= t_max;
+    hit.distance = t;
+    return hit;
+}
+
+// Normal of the side of the box that position lies on
+fn obb_normal(obb: Obb, position: vec3<f32>) -> vec3<f32> {
+    let local = rotate(vec4(-obb.rotation.xyz, obb.rotation.w), position - obb.center) / obb.half_extents;
+    let distance = abs(local);
+    var normal = vec3(0.0, 0.0, sign(local.z));
+    if distance.x >= distance.y && distance.x >= distance.z {
+        normal = vec3(sign(local.x), 0.0, 0.0);
+    } else if distance.y >= distance.z {
+        normal = vec3(0.0, sign(local.y), 0.0);
+    }
+    return rotate(obb.rotation, normal);
+}
+
+// Rotates v by the unit quaternion q
+fn rotate(q: vec4<f32>, v: vec3<f32>) -> vec3<f32> {
+    let t = 2.0 * cross(q.xyz, v);
+    return v + q.w * t + cross(q.xyz, t);
+}
+
 fn sphere_normal(sphere: Sphere, position: vec3<f32>) -> vec3<f32> {
     return normalize(position - sphere.position);
 }
 
+// Texture of the sphere stored at shift in its flags, which is wrapped around it horizontally, white without one.
+// The texture is filtered over the footprint of the pixel, stretched along the surface at grazing angles
+fn sample_texture(sphere: Sphere, shift: u32, normal: vec3<f32>, direction: vec3<f32>, footprint: f32) -> vec3<f32> {
+    let texture = (sphere.flags >> shift) & 0xffu;
+    if texture == 0u || texture > settings.texture_count {
+        return vec3(1.0);
+    }
+    let uv = vec2(0.5 + atan2(normal.z, normal.x) / (2.0 * pi), 0.5 - asin(clamp(normal.y, -1.0, 1.0)) / pi);
+
+    // axes of the footprint on the surface, across and along the direction of the ray
+    let cos_angle = dot(direction, normal);
+    var along = direction - normal * cos_angle;
+    if dot(along, along) < 1e-8 {
+        along = cross(normal, select(vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0), abs(normal.x) > 0.9));
+    }
+    along = normalize(along);
+    let across = cross(normal, along);
+    let scale = footprint / sphere.radius;
+    let ddx = sphere_uv_differential(normal, across * scale);
+    let ddy = sphere_uv_differential(normal, along * scale / max(abs(cos_angle), 0.01));
+
+    // the gradients have to be explicit, because not every path reaches this
+    let texel = textureSampleGrad(textures, texture_sampler, uv, i32(texture - 1u), ddx, ddy);
+    return texel.rgb;
+}
+
+// Change of the texture coordinates of sample_texture when the normal changes by offset
+fn sphere_uv_differential(normal: vec3<f32>, offset: vec3<f32>) -> vec2<f32> {
+    // distance from the poles, where u changes infinitely fast
+    let horizontal = max(normal.x * normal.x + normal.z * normal.z, 1e-6);
+    return vec2(
+        (normal.x * offset.z - normal.z * offset.x) / (horizontal * 2.0 * pi),
+        -offset.y / (sqrt(horizontal) * pi),
+    );
+}
+
 struct Ray {
   origin: vec3<f32>,
   direction: vec3<f32>
@@ -0,0 +1,8 @@
+// Spheres stored in full precision, 64 bytes each
+@group(1)
+@binding(0)
+var<storage, read> spheres: array<Sphere>;
+
+fn load_sphere(index: u32) -> Sphere {
+    return spheres[index];
+}
@@ -0,0 +1,34 @@
+// Spheres stored in half precision, 24 bytes each
+struct PackedSphere {
+  // position and radius as four f16
+  position_radius: vec2<u32>,
+  // emission and metallic as four f16
+  emission_metallic: vec2<u32>,
+  // rgba8 unorm
+  color: u32,
+  flags: u32,
+}
+@group(1)
+@binding(0)
+var<storage, read> spheres: array<PackedSphere>;
+
+fn load_sphere(index: u32) -> Sphere {
+    let stored = spheres[index];
+    let position_radius = vec4(
+        unpack2x16float(stored.position_radius.x),
+        unpack2x16float(stored.position_radius.y),
+    );
+    let emission_metallic = vec4(
+        unpack2x16float(stored.emission_metallic.x),
+        unpack2x16float(stored.emission_metallic.y),
+    );
+
+    var sphere: Sphere;
+    sphere.position = position_radius.xyz;
+    sphere.radius = position_radius.w;
+    sphere.color = unpack4x8unorm(stored.color);
+    sphere.emission = emission_metallic.xyz;
+    sphere.metallic = emission_metallic.w;
+    sphere.flags = stored.flags;
+    return sphere;
+}
@@ -0,0 +1,37 @@
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct FrameStats {
+	last_frame: Option<Instant>,
+	/// Time between the last two frames
+	pub frame_time: Duration,
+	/// Exponential moving average of the frame time in milliseconds
+	average_ms: f32,
+	/// Gpu time of each render pass in milliseconds, see [`Renderer::pass_timings`](crate::renderer::Renderer::pass_timings)
+	pub pass_ms: BTreeMap<&'static str, f32>,
+}
+
+impl FrameStats {
+	/// Weight of the newest frame in the moving average
+	const SMOOTHING: f32 = 0.1;
+
+	/// Records that a new frame started
+	pub fn tick(&mut self) {
+		let now = Instant::now();
+		if let Some(last_frame) = self.last_frame {
+			self.frame_time = now - last_frame;
+			let frame_ms = self.frame_time.as_secs_f32() * 1000.0;
+			self.average_ms = if self.average_ms == 0.0 {
+				frame_ms
+			} else {
+				self.average_ms + (frame_ms - self.average_ms) * Self::SMOOTHING
+			};
+		}
+		self.last_frame = Some(now);
+	}
+
+	pub fn average_ms(&self) -> f32 {
+		self.average_ms
+	}
+}
@@ -0,0 +1,164 @@
+/// Width and height every texture is scaled to, because all layers of an array texture have the same size
+pub(crate) const TEXTURE_SIZE: u32 = 512;
+/// Full mip chain down to a single texel, so distant and grazing spheres don't alias
+const MIP_LEVELS: u32 = TEXTURE_SIZE.ilog2() + 1;
+/// Highest anisotropic filtering, ignored by devices that don't support it
+const MAX_ANISOTROPY: u8 = 16;
+/// Most textures a renderer can hold, so an index plus one fits into 8 bits of the sphere flags.
+/// Lowered to the array layer limit of the device
+pub(crate) const MAX_TEXTURES: u32 = 255;
+const TEXTURE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8UnormSrgb;
+
+/// Images that spheres are textured with, stored as the layers of a single array texture
+/// so the shader can index them without binding array support
+pub(crate) struct MaterialTextures {
+	texture: wgpu::Texture,
+	pub(crate) view: wgpu::TextureView,
+	/// Wraps around the spheres horizontally
+	pub(crate) sampler: wgpu::Sampler,
+	/// Layers in use, the texture has room for more
+	count: u32,
+}
+
+impl MaterialTextures {
+	pub(crate) fn new(device: &wgpu::Device) -> Self {
+		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+			label: Some("Material Sampler"),
+			address_mode_u: wgpu::AddressMode::Repeat,
+			address_mode_v: wgpu::AddressMode::ClampToEdge,
+			mag_filter: wgpu::FilterMode::Linear,
+			min_filter: wgpu::FilterMode::Linear,
+			mipmap_filter: wgpu::FilterMode::Linear,
+			anisotropy_clamp: std::num::NonZeroU8::new(MAX_ANISOTROPY),
+			..Default::default()
+		});
+		let (texture, view) = create_texture(device, 1);
+		Self {
+			texture,
+			view,
+			sampler,
+			count: 0,
+		}
+	}
+
+	/// Most textures that can be added on `device`
+	pub(crate) fn max_count(device: &wgpu::Device) -> u32 {
+		MAX_TEXTURES.min(device.limits().max_texture_array_layers)
+	}
+
+	/// Uploads `image` as the next layer and returns its index.
+	/// Recreates the array texture if it is full, which changes [`MaterialTextures::view`]
+	pub(crate) fn add(
+		&mut self,
+		device: &wgpu::Device,
+		queue: &wgpu::Queue,
+		image: &image::RgbaImage,
+	) -> anyhow::Result<u32> {
+		let max_count = Self::max_count(device);
+		if self.count >= max_count {
+			anyhow::bail!("Can't add more than {max_count} textures");
+		}
+
+		let capacity = self.capacity();
+		if self.count >= capacity {
+			let (texture, view) = create_texture(device, (capacity * 2).min(max_count));
+			let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+				label: Some("Material Texture Copy Encoder"),
+			});
+			for mip_level in 0..MIP_LEVELS {
+				let size = TEXTURE_SIZE >> mip_level;
+				encoder.copy_texture_to_texture(
+					wgpu::ImageCopyTexture {
+						mip_level,
+						..self.texture.as_image_copy()
+					},
+					wgpu::ImageCopyTexture {
+						mip_level,
+						..texture.as_image_copy()
+					},
+					wgpu::Extent3d {
+						width: size,
+						height: size,
+						depth_or_array_layers: self.count,
+					},
+				);
+			}
+			queue.submit(std::iter::once(encoder.finish()));
+			(self.texture, self.view) = (texture, view);
+		}
+
+		// Every mip level is downsampled from the previous one
+		let mut mip = image.clone();
+		for mip_level in 0..MIP_LEVELS {
+			let size = TEXTURE_SIZE >> mip_level;
+			if mip.dimensions() != (size, size) {
+				mip = image::imageops::resize(
+					&mip,
+					size,
+					size,
+					image::imageops::FilterType::Triangle,
+				);
+			}
+			queue.write_texture(
+				wgpu::ImageCopyTexture {
+					texture: &self.texture,
+					mip_level,
+					origin: wgpu::Origin3d {
+						x: 0,
+						y: 0,
+						z: self.count,
+					},
+					aspect: wgpu::TextureAspect::All,
+				},
+				&mip,
+				wgpu::ImageDataLayout {
+					offset: 0,
+					bytes_per_row: std::num::NonZeroU32::new(size * 4),
+					rows_per_image: None,
+				},
+				wgpu::Extent3d {
+					width: size,
+					height: size,
+					depth_or_array_layers: 1,
+				},
+			);
+		}
+
+		self.count += 1;
+		Ok(self.count - 1)
+	}
+
+	pub(crate) fn count(&self) -> u32 {
+		self.count
+	}
+
+	/// Layers of the array texture, used or not
+	pub(crate) fn capacity(&self) -> u32 {
+		self.texture.depth_or_array_layers()
+	}
+}
+
+fn create_texture(device: &wgpu::Device, layers: u32) -> (wgpu::Texture, wgpu::TextureView) {
+	let texture = device.create_texture(&wgpu::TextureDescriptor {
+		label: Some("Material Textures"),
+		size: wgpu::Extent3d {
+			width: TEXTURE_SIZE,
+			height: TEXTURE_SIZE,
+			depth_or_array_layers: layers,
+		},
+		mip_level_count: MIP_LEVELS,
+		sample_count: 1,
+		dimension: wgpu::TextureDimension::D2,
+		format: TEXTURE_FORMAT,
+		usage: wgpu::TextureUsages::TEXTURE_BINDING
+			| wgpu::TextureUsages::COPY_DST
+			| wgpu::TextureUsages::COPY_SRC,
+		view_formats: &[],
+	});
+	// A texture with a single layer would default to a 2d view
+	let view = texture.create_view(&wgpu::TextureViewDescriptor {
+		dimension: Some(wgpu::TextureViewDimension::D2Array),
+		..Default::default()
+	});
+	(texture, view)
+}